@@ -0,0 +1,100 @@
+//! Compares flat A* with hierarchical pathfinding across the map.
+//!
+//! ```sh
+//! cargo bench --bench pathfinding
+//! ```
+
+use bevy_ecs_tilemap::prelude::TilePos;
+use bevy_game::hierarchy::HierarchicalGraph;
+use bevy_game::navigation::Regions;
+use bevy_game::villager::{find_path, MovementCosts, EMPTY_TILE_ID};
+use bevy_game::worldgen::{World, TILEMAP_SIZE};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use grid_2d::{Grid, Size};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+const GRASS_TILE_ID: u16 = 17;
+
+/// Grass with scattered rocks and long walls with a few gaps, so that routes have to detour
+fn obstacle_world() -> World {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let size = Size::new(TILEMAP_SIZE.x, TILEMAP_SIZE.y);
+
+    World {
+        tiles: Grid::new_fn(size, |coord| {
+            let wall = coord.x % 32 == 16 && coord.y % 64 > 4;
+            if wall || rng.gen_bool(0.2) {
+                EMPTY_TILE_ID
+            } else {
+                GRASS_TILE_ID
+            }
+        }),
+    }
+}
+
+/// Routes between walkable tiles close to the given ones that are connected, so that no search gives up early
+fn routes(world: &World, costs: &MovementCosts) -> Vec<(&'static str, TilePos, TilePos)> {
+    let mut regions = Regions::default();
+    regions.update(world, costs);
+
+    let nearby = |tilepos: TilePos| {
+        (0..8).flat_map(move |dy| (0..8).map(move |dx| TilePos::new(tilepos.x + dx, tilepos.y + dy)))
+    };
+    let connected = |start: TilePos, goal: TilePos| {
+        nearby(start)
+            .flat_map(|start| nearby(goal).map(move |goal| (start, goal)))
+            .find(|&(start, goal)| regions.same_region(start, goal))
+            .expect("no connected tiles near the ends of a route")
+    };
+
+    [
+        ("short", TilePos::new(10, 10), TilePos::new(30, 20)),
+        ("across", TilePos::new(2, 128), TilePos::new(240, 130)),
+        ("diagonal", TilePos::new(2, 2), TilePos::new(240, 240)),
+    ]
+    .into_iter()
+    .map(|(name, start, goal)| {
+        let (start, goal) = connected(start, goal);
+        (name, start, goal)
+    })
+    .collect()
+}
+
+fn bench_find_path(c: &mut Criterion) {
+    let world = obstacle_world();
+    let costs = MovementCosts::default();
+    let graph = HierarchicalGraph::new(&world, &costs);
+
+    let mut group = c.benchmark_group("find_path");
+    group.sample_size(20);
+
+    for (name, start, goal) in routes(&world, &costs) {
+        assert!(find_path(&world, &costs, start, goal).is_some());
+        assert!(graph.find_path(start, goal).is_some());
+
+        group.bench_with_input(BenchmarkId::new("astar", name), &(start, goal), |b, &(start, goal)| {
+            b.iter(|| find_path(&world, &costs, start, goal))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("hierarchical", name),
+            &(start, goal),
+            |b, &(start, goal)| b.iter(|| graph.find_path(start, goal)),
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_build(c: &mut Criterion) {
+    let world = obstacle_world();
+    let costs = MovementCosts::default();
+
+    let mut group = c.benchmark_group("hierarchy");
+    group.sample_size(10);
+    group.bench_function("build", |b| b.iter(|| HierarchicalGraph::new(&world, &costs)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_find_path, bench_build);
+criterion_main!(benches);
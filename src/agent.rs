@@ -1,35 +1,126 @@
-/// NOTE: Avoid using action state cancelled
+// NOTE: Avoid using action state cancelled
 use crate::animation::GatheringTag;
-use crate::blackboard::Blackboard;
-use crate::ext::Vec2Ext;
+use crate::behavior::BehaviorAppExt;
+use crate::blackboard::{Blackboard, BlackboardKey, ColonyBlackboard};
+use crate::ext::{TilePosExt, Vec2Ext};
+use crate::items::{Inventory, Item, ItemStack};
+use crate::jobs::{rank_jobs, weigh_priority, Job, JobBoard, JobKind, Skills, WorkPriorities, LOWEST_PRIORITY};
+use crate::navigation::{PathQuery, Pathfinder, Regions};
+use crate::needs::CARRYING_FOOD;
+use crate::planner::{sense, Fact, GoapAction, PlannerAppExt};
 use crate::reservations::{
-    RemoveReservation, Reservable, Reservation, ReservationRequest, ReservationRequestBuilder, Reserved,
+    ReleaseReason, ReleaseReservation, RemoveReservation, Reservable, ReservableTree, ReservationLedger,
+    ReservationRequest, ReservationRequestBuilder, Reserved,
 };
 use crate::states::States::Play;
-use crate::villager::{find_path, Movement};
+use crate::stockpile::{spawn_loose_item, Stockpile, StockpileCell};
+use crate::villager::{Movement, MovementCosts, PathBlocked, PathNotFound};
+use crate::worldgen::World;
+use crate::SimulationSet;
+use bevy::ecs::query::QueryFilter;
 use bevy::prelude::*;
 use bevy_ecs_tilemap::prelude::*;
-use bevy_spatial::kdtree::KDTree2;
 use bevy_spatial::SpatialAccess;
 use big_brain::prelude::*;
-use serde_json::json;
-use std::fmt::Debug;
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter};
 use std::marker::PhantomData;
 
 const MAX_DISTANCE: f32 = 1.0;
 
+/// The bush an agent is on its way to or gathering
+pub const BUSH: BlackboardKey<Entity> = BlackboardKey::new("bush");
+
+/// Whether the agent holds a bush, or could take one from the job board
+pub const BUSH_TO_GATHER: Fact = BlackboardKey::new("bush_to_gather");
+
+/// Whether the agent is standing at the bush it holds
+pub const AT_BUSH: Fact = BlackboardKey::new("at_bush");
+
+/// Where the colony last gathered berries, shared on the `ColonyBlackboard`
+pub const LAST_BERRY_PATCH: BlackboardKey<TilePos> = BlackboardKey::new("last_berry_patch");
+
+/// How long, in seconds, the colony remembers where it last gathered berries
+const BERRY_PATCH_MEMORY: f32 = 120.0;
+
+/// How many of the nearest targets are ranked when picking what to move to
+const NEAREST_TARGETS: usize = 40;
+
+/// The most targets pulled from the reservable tree while looking for ones the agent could reach
+const MAX_NEAREST_TARGETS: usize = 640;
+
+/// How many of the ranked targets are searched for a path, best first, when picking what to move to
+const CANDIDATE_TARGETS: usize = 10;
+
+/// How many berries a gathered bush yields
+const BERRIES_PER_BUSH: u32 = 5;
+
+/// How long, in seconds, it takes to gather a bush
+pub(crate) const GATHER_SECONDS: f32 = 3.0;
+
+/// Work always needs doing, so it scores a steady middle value and, at the default priority, any need that is more
+/// than half empty wins
+const WORK_SCORE: f32 = 0.5;
+
+/// How long, in seconds, an action waits for the pathfinder before giving up on its target
+const PATH_TIMEOUT: f32 = 5.0;
+
 #[derive(Clone, Component, Debug)]
 pub struct Bush;
 
+/// Something that can be designated for chopping.
+///
+/// Worldgen does not grow any trees yet, so chopping designations do not post any jobs until it does.
+#[derive(Clone, Component, Debug)]
+pub struct Tree;
+
+/// Something that can be designated for mining
+#[derive(Clone, Component, Debug)]
+pub struct Rock;
+
 pub struct AgentPlugin;
 
 impl Plugin for AgentPlugin {
     fn build(&self, app: &mut App) {
+        app.register_scorer("WorkNeed", WorkNeedScorer)
+            .register_action("FindBush", FindTarget::<Bush, With<Reservable>>::new(BUSH))
+            .register_action("ReserveBush", Reserve::new(BUSH))
+            .register_action("MoveToBush", MoveTo::new(BUSH).while_reserved())
+            .register_action("Gather", GatherAction)
+            .register_goap_action(
+                GoapAction::new(
+                    "ReachBush",
+                    4,
+                    Steps::build()
+                        .label("ReachBush")
+                        .step(FindTarget::<Bush, With<Reservable>>::new(BUSH))
+                        .step(Reserve::new(BUSH))
+                        .step(MoveTo::new(BUSH).while_reserved()),
+                )
+                .requires(BUSH_TO_GATHER, true)
+                .causes(AT_BUSH, true),
+            )
+            .register_goap_action(
+                GoapAction::new("Gather", 2, GatherAction)
+                    .requires(AT_BUSH, true)
+                    .causes(AT_BUSH, false)
+                    .causes(CARRYING_FOOD, true),
+            );
+
         app.add_systems(
-            PreUpdate,
+            FixedUpdate,
             (
-                (move_to_nearest_system::<Bush>, gather_action_system).in_set(BigBrainSet::Actions),
+                (
+                    find_target_system::<Bush, With<Reservable>>,
+                    reserve_action_system,
+                    move_to_action_system,
+                    gather_action_system,
+                )
+                    .chain()
+                    .in_set(SimulationSet::Work),
                 (work_need_scorer_system,).in_set(BigBrainSet::Scorers),
+                sense_bushes_system.in_set(SimulationSet::Sense),
+                fail_blocked_actions_system.in_set(SimulationSet::Prepare),
             )
                 .run_if(in_state(Play)),
         );
@@ -38,124 +129,257 @@ impl Plugin for AgentPlugin {
     }
 }
 
-#[derive(Clone, Component, Debug, ActionBuilder)]
-pub struct MoveToNearest<T: Clone + Component + Debug> {
-    _marker: PhantomData<T>,
-    goal: Option<Vec2>,
+/// Finds the best target matching `F` the actor can reach and writes it to the blackboard under `key`.
+///
+/// A target the actor already holds a reservation on is picked up again first. Otherwise the nearest reservable
+/// targets in the actor's region are ranked: targets with a posted job are only considered if the job suits the actor,
+/// and are ranked the way jobs are, ahead of targets without one, which are ranked by distance.
+#[derive(Component)]
+pub struct FindTarget<T: Component, F: QueryFilter + 'static = ()> {
+    key: BlackboardKey<Entity>,
+    /// When this action started waiting on the pathfinder, measured by `Time::elapsed_seconds`
+    waiting_since: Option<f32>,
+    /// Candidates found to be unreachable, which are left out from then on so that the ones after them get searched
+    unreachable: HashSet<Entity>,
+    _marker: PhantomData<fn() -> (T, F)>,
 }
 
-impl<T: Clone + Component + Debug> MoveToNearest<T> {
-    pub fn new() -> Self {
+impl<T: Component, F: QueryFilter + 'static> FindTarget<T, F> {
+    pub fn new(key: BlackboardKey<Entity>) -> Self {
         Self {
+            key,
+            waiting_since: None,
+            unreachable: HashSet::new(),
             _marker: PhantomData,
-            goal: None,
         }
     }
 }
 
-impl<T: Clone + Component + Debug> Default for MoveToNearest<T> {
-    fn default() -> Self {
-        Self::new()
+impl<T: Component, F: QueryFilter + 'static> Clone for FindTarget<T, F> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key,
+            waiting_since: self.waiting_since,
+            unreachable: self.unreachable.clone(),
+            _marker: PhantomData,
+        }
     }
 }
 
-pub fn move_to_nearest_system<T: Clone + Component + Debug>(
-    world: Res<crate::worldgen::World>,
-    reservables: Res<KDTree2<Reservable>>,
-    reserved_tiles: Query<(Entity, &mut TilePos), (With<T>, With<Reserved>)>,
-    mut agents_without_reservation: Query<
-        (&mut Blackboard, &mut Transform, &mut Movement),
-        (With<HasThinker>, Without<Reservation>),
-    >,
-    mut agents_with_reservation: Query<
-        (&mut Blackboard, &mut Transform, &mut Movement, &Reservation),
-        (With<HasThinker>,),
-    >,
-    mut action_query: Query<(&Actor, &mut ActionState, &mut MoveToNearest<T>, &ActionSpan)>,
-    mut reservation_request_writer: EventWriter<ReservationRequest>,
+impl<T: Component, F: QueryFilter + 'static> Debug for FindTarget<T, F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FindTarget")
+            .field("target", &std::any::type_name::<T>())
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+impl<T: Component, F: QueryFilter + 'static> ActionBuilder for FindTarget<T, F> {
+    fn build(&self, cmd: &mut Commands, action: Entity, _actor: Entity) {
+        cmd.entity(action).insert(self.clone());
+    }
+}
+
+/// Returns the target of type `T` an agent holds a reservation on, if any, and where it is
+pub(crate) fn held_target<T: Component>(
+    ledger: &ReservationLedger,
+    agent: Entity,
+    targets: &Query<&TilePos, With<T>>,
+) -> Option<(Entity, TilePos)> {
+    ledger
+        .reservations_of(agent)
+        .filter_map(|reservation| reservation.target.entity())
+        .find_map(|target| targets.get(target).ok().map(|&tilepos| (target, tilepos)))
+}
+
+pub fn find_target_system<T: Component, F: QueryFilter + 'static>(
+    time: Res<Time>,
+    mut pathfinder: ResMut<Pathfinder>,
+    regions: Res<Regions>,
+    reservables: Res<ReservableTree>,
+    ledger: Res<ReservationLedger>,
+    board: Res<JobBoard>,
+    jobs: Query<&Job>,
+    held: Query<&TilePos, With<T>>,
+    candidates: Query<&TilePos, (With<T>, Without<Reserved>, F)>,
+    mut agents: Query<(&mut Blackboard, &Transform, &WorkPriorities, &Skills)>,
+    mut action_query: Query<(&Actor, &mut ActionState, &mut FindTarget<T, F>, &ActionSpan)>,
+    mut path_not_found_writer: EventWriter<PathNotFound>,
 ) {
-    for (actor, mut action_state, mut move_to, span) in &mut action_query {
+    for (actor, mut action_state, mut find, span) in &mut action_query {
         let _guard = span.span().enter();
 
+        let Ok((mut blackboard, transform, priorities, skills)) = agents.get_mut(actor.0) else {
+            *action_state = ActionState::Failure;
+            continue;
+        };
+
         match *action_state {
             ActionState::Requested => {
-                if let Ok((_, transform, _)) = agents_without_reservation.get_mut(actor.0) {
-                    // Get k nearest reservable entities
-                    let targets = reservables.k_nearest_neighbour(transform.translation.xy(), 10);
-
-                    // Attempt to search the nearest 10 possible targets
-                    for (target_position, target_entity) in targets.iter() {
-                        let path_option = find_path(
-                            &world,
-                            transform.translation.xy().to_tilepos(),
-                            target_position.xy().to_tilepos(),
-                        );
+                if let Some((target, _)) = held_target(&ledger, actor.0, &held) {
+                    blackboard.insert(find.key, target);
+                    *action_state = ActionState::Success;
+                    continue;
+                }
 
-                        if path_option.is_some() {
-                            trace!(
-                                "Found reachable {:?} (World Position {}, {}) - attempting a to create a reservation on {:?} for {:?}",
-                                std::any::type_name::<T>(),
-                                target_position.x,
-                                target_position.y,
-                                target_position,
-                                actor.0
-                            );
-
-                            reservation_request_writer.send(
-                                ReservationRequestBuilder::default()
-                                    .requester(actor.0)
-                                    .target(target_entity.unwrap())
-                                    .build()
-                                    .unwrap(),
-                            );
-
-                            // This shouldn't be here but whatever
-                            move_to.goal = Some(target_position.xy());
-
-                            *action_state = ActionState::Requested;
-                            return;
+                let waiting_since = *find.waiting_since.get_or_insert(time.elapsed_seconds());
+                if time.elapsed_seconds() - waiting_since > PATH_TIMEOUT {
+                    debug!("Timed out waiting for a path to {:?}", std::any::type_name::<T>());
+                    find.waiting_since = None;
+                    *action_state = ActionState::Failure;
+                    continue;
+                }
+
+                // Targets in other regions can never be reached, so they are skipped without searching for a path
+                let start = transform.translation.xy().to_tilepos();
+                let reachable = nearest_in_region(&reservables, &regions, transform.translation.xy(), |target| {
+                    let tilepos = candidates.get(target).ok()?;
+                    (!find.unreachable.contains(&target)).then_some(*tilepos)
+                });
+
+                let (posted, unposted): (Vec<_>, Vec<_>) = reachable
+                    .into_iter()
+                    .partition(|&(target, _)| board.job_for(target).is_some());
+                let posted = posted
+                    .into_iter()
+                    .filter_map(|(target, _)| jobs.get(board.job_for(target)?).ok());
+
+                // Targets without a job are already nearest first
+                let ranked = rank_jobs(posted, priorities, skills, start)
+                    .into_iter()
+                    .map(|job| (job.target, job.location))
+                    .chain(unposted);
+
+                if ranked.clone().next().is_none() {
+                    find.waiting_since = None;
+                    *action_state = ActionState::Failure;
+                    continue;
+                }
+
+                // Attempt to search the best targets first
+                for (target, goal) in ranked.take(CANDIDATE_TARGETS) {
+                    match pathfinder.query(start, goal) {
+                        PathQuery::Found(_) => {
+                            trace!("Found reachable {:?} at {:?}", std::any::type_name::<T>(), goal);
+                            blackboard.insert(find.key, target);
+                            find.waiting_since = None;
+                            *action_state = ActionState::Success;
+                            break;
+                        }
+                        PathQuery::NotFound => {
+                            find.unreachable.insert(target);
+                            path_not_found_writer.send(PathNotFound {
+                                agent: actor.0,
+                                start,
+                                goal,
+                            });
                         }
+                        // Wait for the closer target rather than settling for a further one
+                        PathQuery::Pending => break,
                     }
                 }
+            }
+            ActionState::Cancelled => {
+                find.waiting_since = None;
+                *action_state = ActionState::Failure;
+            }
+            _ => {}
+        }
+    }
+}
 
-                if let Ok(agent) = agents_with_reservation.get_mut(actor.0) {
-                    let mut blackboard = agent.0;
-                    let agent_transform = agent.1;
-                    let mut agent_movement = agent.2;
-                    let reservation = agent.3;
+/// Returns up to `NEAREST_TARGETS` of the reservables nearest to `position` that `candidate` gives a tile for and
+/// that are in the same region, nearest first.
+///
+/// More of the nearest reservables are looked at until enough are found or `MAX_NEAREST_TARGETS` were looked at,
+/// since most of the nearest ones may be of another kind or cut off from the agent.
+fn nearest_in_region(
+    reservables: &ReservableTree,
+    regions: &Regions,
+    position: Vec2,
+    candidate: impl Fn(Entity) -> Option<TilePos>,
+) -> Vec<(Entity, TilePos)> {
+    let start = position.to_tilepos();
+    let mut k = NEAREST_TARGETS;
+
+    loop {
+        let nearest = reservables.k_nearest_neighbour(position, k);
+        let exhausted = nearest.len() < k || k >= MAX_NEAREST_TARGETS;
+
+        let targets: Vec<_> = nearest
+            .into_iter()
+            .filter_map(|(_, target)| {
+                let target = target?;
+                Some((target, candidate(target)?))
+            })
+            .filter(|&(_, tilepos)| regions.same_region(start, tilepos))
+            .take(NEAREST_TARGETS)
+            .collect();
+
+        if targets.len() == NEAREST_TARGETS || exhausted {
+            return targets;
+        }
 
-                    let start_coord = agent_transform.translation.xy().to_tilepos();
-                    let goal_tile = reserved_tiles.get(reservation.target);
+        k *= 4;
+    }
+}
 
-                    if let Ok((goal_tile_entity, &goal_tile_position)) = goal_tile {
-                        let path_option = find_path(&world, start_coord, goal_tile_position);
+/// Reserves the target stored on the blackboard under `key`, failing if it cannot be reserved
+#[derive(Clone, Component, Debug, ActionBuilder)]
+pub struct Reserve {
+    key: BlackboardKey<Entity>,
+}
 
-                        if let Some(mut path) = path_option {
-                            // We don't want to include the first goal if it is the same as the start
-                            if path.first() == Some(&start_coord) {
-                                path.remove(0);
-                            }
+impl Reserve {
+    pub fn new(key: BlackboardKey<Entity>) -> Self {
+        Self { key }
+    }
+}
 
-                            trace!("Set path to {:?}", std::any::type_name::<T>());
-                            agent_movement.path = path;
-                            *action_state = ActionState::Executing;
-                            blackboard.insert("bush", json!(goal_tile_entity));
-                        }
-                    } else {
-                        *action_state = ActionState::Failure;
-                    }
+pub fn reserve_action_system(
+    ledger: Res<ReservationLedger>,
+    agents: Query<&Blackboard>,
+    mut action_query: Query<(Entity, &Actor, &mut ActionState, &Reserve, &ActionSpan)>,
+    mut reservation_request_writer: EventWriter<ReservationRequest>,
+) {
+    for (action, actor, mut action_state, reserve, span) in &mut action_query {
+        let _guard = span.span().enter();
+
+        let Some(target) = agents
+            .get(actor.0)
+            .ok()
+            .and_then(|blackboard| blackboard.get(reserve.key))
+        else {
+            *action_state = ActionState::Failure;
+            continue;
+        };
+
+        match *action_state {
+            ActionState::Requested => {
+                // A reservation restored from a save is asked for again, so that it is tied to this action
+                if ledger.is_claimed_by(target, actor.0) {
+                    *action_state = ActionState::Success;
+                    continue;
                 }
+
+                reservation_request_writer.send(
+                    ReservationRequestBuilder::default()
+                        .requester(actor.0)
+                        .action(action)
+                        .target(target)
+                        .build()
+                        .unwrap(),
+                );
+                *action_state = ActionState::Executing;
             }
             ActionState::Executing => {
-                let (_, actor_transform, _actor_movement, _reservation) =
-                    agents_with_reservation.get_mut(actor.0).unwrap();
-                let delta = move_to.goal.unwrap() - actor_transform.translation.xy();
-                let distance = delta.length();
-                if distance > MAX_DISTANCE {
-                    // Movement should be handled by the movement system
+                // Requests are settled after the actions run, so by now the reservation was either made or turned down
+                *action_state = if ledger.is_reserved_by(target, actor.0) {
+                    ActionState::Success
                 } else {
-                    *action_state = ActionState::Success;
-                }
+                    ActionState::Failure
+                };
             }
             ActionState::Cancelled => {
                 *action_state = ActionState::Failure;
@@ -165,12 +389,236 @@ pub fn move_to_nearest_system<T: Clone + Component + Debug>(
     }
 }
 
+/// Walks to the target stored on the blackboard under `key`, finding a new path whenever the target moves or the
+/// path runs out before arriving.
+///
+/// Stockpile cells are walked to along the flow field of their zone, which everyone heading there shares.
+#[derive(Clone, Component, Debug, ActionBuilder)]
+pub struct MoveTo {
+    key: BlackboardKey<Entity>,
+    /// Whether to give up as soon as the actor no longer holds a reservation on the target
+    reserved: bool,
+    /// The tile the current path leads to
+    goal: Option<TilePos>,
+    /// When this action started waiting on the pathfinder, measured by `Time::elapsed_seconds`
+    waiting_since: Option<f32>,
+}
+
+impl MoveTo {
+    pub fn new(key: BlackboardKey<Entity>) -> Self {
+        Self {
+            key,
+            reserved: false,
+            goal: None,
+            waiting_since: None,
+        }
+    }
+
+    /// Gives up on the target when its reservation is lost, for example because the designation was cancelled.
+    pub fn while_reserved(mut self) -> Self {
+        self.reserved = true;
+        self
+    }
+}
+
+pub fn move_to_action_system(
+    time: Res<Time>,
+    world: Res<World>,
+    costs: Res<MovementCosts>,
+    mut pathfinder: ResMut<Pathfinder>,
+    ledger: Res<ReservationLedger>,
+    targets: Query<&TilePos>,
+    cells: Query<&StockpileCell>,
+    zones: Query<&Stockpile>,
+    mut agents: Query<(&Blackboard, &Transform, &mut Movement)>,
+    mut action_query: Query<(&Actor, &mut ActionState, &mut MoveTo, &ActionSpan)>,
+    mut path_not_found_writer: EventWriter<PathNotFound>,
+) {
+    for (actor, mut action_state, mut move_to, span) in &mut action_query {
+        let _guard = span.span().enter();
+
+        let Ok((blackboard, transform, mut movement)) = agents.get_mut(actor.0) else {
+            *action_state = ActionState::Failure;
+            continue;
+        };
+
+        if *action_state == ActionState::Cancelled {
+            move_to.waiting_since = None;
+            movement.path.clear();
+            *action_state = ActionState::Failure;
+            continue;
+        }
+
+        if !matches!(*action_state, ActionState::Requested | ActionState::Executing) {
+            continue;
+        }
+
+        let target = blackboard.get(move_to.key);
+        let goal = target.and_then(|target| targets.get(target).ok());
+        let lost = move_to.reserved && !target.is_some_and(|target| ledger.is_reserved_by(target, actor.0));
+        let Some(&goal) = goal.filter(|_| !lost) else {
+            // The target is gone, or was taken away
+            debug!("Lost the target to move to");
+            move_to.waiting_since = None;
+            movement.path.clear();
+            *action_state = ActionState::Failure;
+            continue;
+        };
+
+        let start = transform.translation.xy().to_tilepos();
+        if goal.to_world_space().distance(transform.translation.xy()) <= MAX_DISTANCE {
+            movement.path.clear();
+            *action_state = ActionState::Success;
+            continue;
+        }
+
+        // Keep following the current path as long as it still leads to the target
+        if *action_state == ActionState::Executing && move_to.goal == Some(goal) && !movement.path.is_empty() {
+            continue;
+        }
+
+        let waiting_since = *move_to.waiting_since.get_or_insert(time.elapsed_seconds());
+        if time.elapsed_seconds() - waiting_since > PATH_TIMEOUT {
+            debug!("Timed out waiting for a path to {:?}", goal);
+            move_to.waiting_since = None;
+            *action_state = ActionState::Failure;
+            continue;
+        }
+
+        let zone = target
+            .and_then(|target| cells.get(target).ok())
+            .and_then(|cell| zones.get(cell.zone).ok());
+        let route = match zone {
+            Some(zone) => pathfinder
+                .path_into(&world, &costs, start, &zone.tiles(), goal)
+                .map_or(PathQuery::NotFound, PathQuery::Found),
+            None => pathfinder.query(start, goal),
+        };
+
+        match route {
+            PathQuery::Found(mut path) => {
+                // We don't want to include the first goal if it is the same as the start, unless it is the goal and
+                // the actor still has to walk to its centre
+                if path.len() > 1 && path.first() == Some(&start) {
+                    path.remove(0);
+                }
+
+                trace!("Set path to {:?}", goal);
+                movement.path = path;
+                move_to.goal = Some(goal);
+                move_to.waiting_since = None;
+                *action_state = ActionState::Executing;
+            }
+            PathQuery::NotFound => {
+                path_not_found_writer.send(PathNotFound {
+                    agent: actor.0,
+                    start,
+                    goal,
+                });
+                move_to.waiting_since = None;
+                movement.path.clear();
+                *action_state = ActionState::Failure;
+            }
+            // Keep walking the old path, if there is one, while the new one is found
+            PathQuery::Pending => {}
+        }
+    }
+}
+
+/// Fails whichever action was moving an agent whose path became blocked, so that its thinker picks something else.
+///
+/// Only actions without children are failed, the sequences and plans they belong to then fail or replan on their
+/// own.
+fn fail_blocked_actions_system(
+    mut path_blocked: EventReader<PathBlocked>,
+    mut actions: Query<(&Actor, &mut ActionState), Without<Children>>,
+) {
+    let blocked: HashSet<Entity> = path_blocked.read().map(|event| event.agent).collect();
+    if blocked.is_empty() {
+        return;
+    }
+
+    for (actor, mut action_state) in &mut actions {
+        if blocked.contains(&actor.0) && *action_state == ActionState::Executing {
+            *action_state = ActionState::Failure;
+        }
+    }
+}
+
+/// Returns whether there is a gathering job on the board that an agent with these priorities and skills would take and
+/// could reach from `position`
+fn open_bush_job(
+    jobs: &Query<&Job>,
+    open_bushes: &Query<(), (With<Bush>, With<Reservable>)>,
+    regions: &Regions,
+    priorities: &WorkPriorities,
+    skills: &Skills,
+    position: TilePos,
+) -> bool {
+    jobs.iter().any(|job| {
+        open_bushes.contains(job.target) && job.suits(priorities, skills) && regions.same_region(position, job.location)
+    })
+}
+
+/// Keeps `BUSH_TO_GATHER` and `AT_BUSH` up to date on every agent's blackboard.
+///
+/// Bushes in other regions do not count, since the agent could never reach them.
+fn sense_bushes_system(
+    jobs: Query<&Job>,
+    ledger: Res<ReservationLedger>,
+    regions: Res<Regions>,
+    open_bushes: Query<(), (With<Bush>, With<Reservable>)>,
+    bushes: Query<&TilePos, With<Bush>>,
+    mut agents: Query<(Entity, &mut Blackboard, &Transform, &WorkPriorities, &Skills)>,
+) {
+    for (agent, mut blackboard, transform, priorities, skills) in &mut agents {
+        let held = held_target(&ledger, agent, &bushes);
+        let position = transform.translation.xy().to_tilepos();
+        let open = || open_bush_job(&jobs, &open_bushes, &regions, priorities, skills, position);
+        let at_bush = held
+            .is_some_and(|(_, tilepos)| tilepos.to_world_space().distance(transform.translation.xy()) <= MAX_DISTANCE);
+
+        sense(&mut blackboard, BUSH_TO_GATHER, held.is_some() || open());
+        sense(&mut blackboard, AT_BUSH, at_bush);
+    }
+}
+
 #[derive(Clone, Component, Debug, ScorerBuilder)]
 pub struct WorkNeedScorer;
 
-pub fn work_need_scorer_system(mut query: Query<(&Actor, &mut Score), With<WorkNeedScorer>>) {
-    for (Actor(_actor), mut score) in &mut query {
-        score.set(1.0);
+/// Scores work while the actor has a bush left to finish or there is a gathering job on the board it would take and
+/// could reach, weighed by how highly the actor prioritizes gathering
+pub fn work_need_scorer_system(
+    jobs: Query<&Job>,
+    ledger: Res<ReservationLedger>,
+    regions: Res<Regions>,
+    open_bushes: Query<(), (With<Bush>, With<Reservable>)>,
+    bushes: Query<&TilePos, With<Bush>>,
+    agents: Query<(&WorkPriorities, &Skills, &Transform)>,
+    mut query: Query<(&Actor, &mut Score), With<WorkNeedScorer>>,
+) {
+    for (Actor(actor), mut work_score) in &mut query {
+        let Ok((priorities, skills, transform)) = agents.get(*actor) else {
+            work_score.set(0.0);
+            continue;
+        };
+
+        if held_target(&ledger, *actor, &bushes).is_some() {
+            // A bush that was started is finished even if gathering has since been switched off
+            let priority = priorities.get(JobKind::Gather).unwrap_or(LOWEST_PRIORITY);
+            work_score.set(weigh_priority(priority, WORK_SCORE));
+        } else if open_bush_job(
+            &jobs,
+            &open_bushes,
+            &regions,
+            priorities,
+            skills,
+            transform.translation.xy().to_tilepos(),
+        ) {
+            work_score.set(priorities.weigh(JobKind::Gather, WORK_SCORE));
+        } else {
+            work_score.set(0.0);
+        }
     }
 }
 
@@ -183,13 +631,13 @@ pub struct GatheringTimer(Timer);
 pub fn gather_action_system(
     time: Res<Time>,
     mut commands: Commands,
-    mut agents: Query<
-        (&mut Blackboard, &mut Transform, &mut Movement, &mut GatheringTimer),
-        (With<HasThinker>, Without<Bush>),
-    >,
+    ledger: Res<ReservationLedger>,
+    mut colony: ResMut<ColonyBlackboard>,
+    mut agents: Query<(&mut Blackboard, &mut Inventory, &mut GatheringTimer), (With<HasThinker>, Without<Bush>)>,
     mut action_query: Query<(&Actor, &mut ActionState, &GatherAction, &ActionSpan)>,
-    tilepos_q: Query<&TilePos>,
+    bushes: Query<&TilePos, With<Bush>>,
     mut remove_reservation_event_writer: EventWriter<RemoveReservation>,
+    mut release_writer: EventWriter<ReleaseReservation>,
 ) {
     for (actor, mut action_state, _action, span) in &mut action_query {
         let _guard = span.span().enter();
@@ -202,47 +650,81 @@ pub fn gather_action_system(
                 // Add a timer for how long to stay gathering
                 commands
                     .entity(actor.0)
-                    .insert(GatheringTimer(Timer::from_seconds(3.0, TimerMode::Once)));
+                    .insert(GatheringTimer(Timer::from_seconds(GATHER_SECONDS, TimerMode::Once)));
 
                 *action_state = ActionState::Executing;
             }
             ActionState::Executing => {
                 // Update the timer
-                if let Ok((mut blackboard, _, _, mut timer)) = agents.get_mut(actor.0) {
+                if let Ok((mut blackboard, mut inventory, mut timer)) = agents.get_mut(actor.0) {
+                    let Some((bush, tilepos)) = held_target(&ledger, actor.0, &bushes) else {
+                        // The bush was cancelled while it was being gathered
+                        blackboard.remove(BUSH);
+                        commands.entity(actor.0).remove::<(GatheringTag, GatheringTimer)>();
+                        *action_state = ActionState::Failure;
+                        continue;
+                    };
+
                     timer.0.tick(time.delta());
 
                     if timer.0.finished() {
-                        let value = blackboard.get("bush");
-                        if let Some(raw_entity_number) = value.as_number() {
-                            let raw_entity_u64 = raw_entity_number.as_u64().unwrap();
-                            let raw_entity_u32 = raw_entity_u64 as u32;
-
-                            let entity_option = commands.get_entity(Entity::from_raw(raw_entity_u32));
-
-                            if let Some(entity) = entity_option {
-                                let entity_id = entity.id(); // Store the entity ID to avoid multiple mutable borrows
-                                commands.entity(entity_id).despawn();
-                                *action_state = ActionState::Success;
-
-                                let tilepos = *tilepos_q.get(entity_id).unwrap();
-                                remove_reservation_event_writer.send(RemoveReservation { tilepos });
-                            } else {
-                                *action_state = ActionState::Failure;
-                            }
-                        }
+                        harvest_bush(
+                            &mut commands,
+                            bush,
+                            tilepos,
+                            &mut inventory,
+                            &mut colony,
+                            &mut remove_reservation_event_writer,
+                        );
 
-                        blackboard.remove("bush");
+                        blackboard.remove(BUSH);
                         commands.entity(actor.0).remove::<GatheringTag>();
                         commands.entity(actor.0).remove::<GatheringTimer>();
-                        commands.entity(actor.0).remove::<Reservation>();
+                        release_writer.send(ReleaseReservation {
+                            owner: actor.0,
+                            target: Some(bush.into()),
+                            reason: ReleaseReason::Done,
+                        });
+                        *action_state = ActionState::Success;
                     }
                 }
             }
+            ActionState::Cancelled => {
+                // Failing gives the bush back for anyone to gather
+                commands.entity(actor.0).remove::<GatheringTag>();
+                commands.entity(actor.0).remove::<GatheringTimer>();
+                *action_state = ActionState::Failure;
+            }
             _ => {}
         }
     }
 }
 
+/// Despawns a gathered bush and gives its berries to the gatherer, leaving whatever cannot be carried on the ground
+pub(crate) fn harvest_bush(
+    commands: &mut Commands,
+    bush: Entity,
+    tilepos: TilePos,
+    inventory: &mut Inventory,
+    colony: &mut ColonyBlackboard,
+    remove_reservation_writer: &mut EventWriter<RemoveReservation>,
+) {
+    commands.entity(bush).despawn();
+    colony.0.insert_for(LAST_BERRY_PATCH, tilepos, BERRY_PATCH_MEMORY);
+
+    // Whatever cannot be carried is left on the ground for a hauler
+    let left_over = inventory.add(Item::Berries, BERRIES_PER_BUSH);
+    if left_over > 0 {
+        let stack = ItemStack {
+            item: Item::Berries,
+            count: left_over,
+        };
+        spawn_loose_item(commands, tilepos, stack);
+    }
+
+    remove_reservation_writer.send(RemoveReservation { tilepos });
+}
+
 fn update_z_system(mut query: Query<&mut Transform, With<HasThinker>>) {
     for mut transform in query.iter_mut() {
         // Inverse the y value relationship to z and add to the base value of 10.0
@@ -1,4 +1,4 @@
-use crate::villager::{movement_system, AnimationIndices, Movement};
+use crate::villager::{AnimationIndices, Movement};
 use bevy::prelude::*;
 use bevy_ecs_tilemap::helpers::square_grid::neighbors::SquareDirection;
 use seldom_state::prelude::*;
@@ -8,7 +8,7 @@ pub struct AnimationPlugin;
 impl Plugin for AnimationPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Update, update_animation_indices_in_idle_state);
-        app.add_systems(Update, update_animation_indices_in_moving_state.after(movement_system));
+        app.add_systems(Update, update_animation_indices_in_moving_state);
         app.add_systems(Update, update_animation_indices_in_gathering_state);
     }
 }
@@ -0,0 +1,355 @@
+use crate::agent::GatheringTimer;
+use crate::animation::GatheringTag;
+use crate::reservations::{ReleaseReason, ReleaseReservation};
+use crate::states::States::Play;
+use crate::SimulationSet;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::prelude::*;
+use big_brain::pickers::{FirstToScore, Highest};
+use big_brain::prelude::*;
+use big_brain::thinker::ThinkerBuilder;
+use extend::ext;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+/// Where villager professions are defined, relative to the assets folder
+pub const BEHAVIORS_PATH: &str = "villagers.behaviors.ron";
+
+/// Builds villager thinkers from the professions in `BEHAVIORS_PATH`, rebuilding them whenever the file changes.
+///
+/// Run with the `dev` feature to have changes to the file picked up while the game is running.
+pub struct BehaviorPlugin;
+
+impl Plugin for BehaviorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<BehaviorDefinitions>()
+            .init_asset_loader::<BehaviorDefinitionsLoader>()
+            .init_resource::<BehaviorRegistry>()
+            .register_type::<Profession>()
+            .add_systems(Startup, load_behaviors_system)
+            .add_systems(
+                FixedUpdate,
+                apply_behaviors_system
+                    .in_set(SimulationSet::Settle)
+                    .run_if(in_state(Play)),
+            );
+    }
+}
+
+/// Which profession in `BEHAVIORS_PATH` a villager's thinker is built from
+#[derive(Clone, Component, Debug, Deserialize, PartialEq, Reflect, Serialize)]
+#[reflect(Component)]
+pub struct Profession(pub String);
+
+impl Default for Profession {
+    fn default() -> Self {
+        Self("farmer".to_string())
+    }
+}
+
+/// Every villager profession, as loaded from `BEHAVIORS_PATH`
+#[derive(Asset, Debug, Deserialize, TypePath)]
+pub struct BehaviorDefinitions {
+    pub professions: Vec<ThinkerDefinition>,
+}
+
+/// A thinker in terms of the names its scorers and actions are registered under
+#[derive(Clone, Debug, Deserialize)]
+pub struct ThinkerDefinition {
+    pub name: String,
+    pub picker: PickerDefinition,
+    /// Earlier choices win ties
+    pub choices: Vec<ChoiceDefinition>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum PickerDefinition {
+    Highest,
+    FirstToScore(f32),
+}
+
+/// Runs `steps` in order while `scorer` wins, a single step is run as is
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChoiceDefinition {
+    pub scorer: String,
+    #[serde(default)]
+    pub label: Option<String>,
+    pub steps: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BehaviorError {
+    UnknownScorer(String),
+    UnknownAction(String),
+    /// A choice, named by its scorer, has nothing to do
+    NoSteps(String),
+}
+
+impl Display for BehaviorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BehaviorError::UnknownScorer(name) => write!(f, "no scorer is registered as {}", name),
+            BehaviorError::UnknownAction(name) => write!(f, "no action is registered as {}", name),
+            BehaviorError::NoSteps(scorer) => write!(f, "the choice scored by {} has no steps", scorer),
+        }
+    }
+}
+
+impl std::error::Error for BehaviorError {}
+
+/// The scorers and actions behavior definitions can use, by name
+#[derive(Default, Resource)]
+pub struct BehaviorRegistry {
+    scorers: HashMap<String, Arc<dyn ScorerBuilder>>,
+    actions: HashMap<String, Arc<dyn ActionBuilder>>,
+}
+
+impl BehaviorRegistry {
+    pub fn register_scorer(&mut self, name: &str, scorer: impl ScorerBuilder + 'static) {
+        self.scorers.insert(name.to_string(), Arc::new(scorer));
+    }
+
+    pub fn register_action(&mut self, name: &str, action: impl ActionBuilder + 'static) {
+        self.actions.insert(name.to_string(), Arc::new(action));
+    }
+
+    /// Builds a thinker from a definition, failing on the first scorer or action that is not registered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bevy::prelude::*;
+    /// use bevy_game::behavior::{BehaviorError, BehaviorRegistry, ThinkerDefinition};
+    /// use big_brain::prelude::*;
+    ///
+    /// #[derive(Clone, Component, Debug, ActionBuilder)]
+    /// struct Wander;
+    ///
+    /// let mut registry = BehaviorRegistry::default();
+    /// registry.register_scorer("Always", FixedScore::build(0.5));
+    /// registry.register_action("Wander", Wander);
+    ///
+    /// let definition: ThinkerDefinition = ron::from_str(
+    ///     r#"(name: "wanderer", picker: Highest, choices: [(scorer: "Always", steps: ["Wander", "Wander"])])"#,
+    /// )
+    /// .unwrap();
+    /// assert!(registry.thinker(&definition).is_ok());
+    ///
+    /// let definition: ThinkerDefinition = ron::from_str(
+    ///     r#"(name: "sleeper", picker: FirstToScore(0.8), choices: [(scorer: "Always", steps: ["Sleep"])])"#,
+    /// )
+    /// .unwrap();
+    /// assert_eq!(
+    ///     registry.thinker(&definition).err(),
+    ///     Some(BehaviorError::UnknownAction("Sleep".to_string()))
+    /// );
+    /// ```
+    pub fn thinker(&self, definition: &ThinkerDefinition) -> Result<ThinkerBuilder, BehaviorError> {
+        let mut thinker = Thinker::build().label(&definition.name);
+        thinker = match definition.picker {
+            PickerDefinition::Highest => thinker.picker(Highest),
+            PickerDefinition::FirstToScore(threshold) => thinker.picker(FirstToScore { threshold }),
+        };
+
+        for choice in &definition.choices {
+            let scorer = self
+                .scorers
+                .get(&choice.scorer)
+                .cloned()
+                .ok_or_else(|| BehaviorError::UnknownScorer(choice.scorer.clone()))?;
+
+            let actions = choice
+                .steps
+                .iter()
+                .map(|name| {
+                    self.actions
+                        .get(name)
+                        .cloned()
+                        .ok_or_else(|| BehaviorError::UnknownAction(name.clone()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            thinker = match actions.as_slice() {
+                [] => return Err(BehaviorError::NoSteps(choice.scorer.clone())),
+                [action] => thinker.when(RegisteredScorer(scorer), RegisteredAction(action.clone())),
+                _ => {
+                    let label = choice.label.as_deref().unwrap_or(&choice.scorer);
+                    let steps = actions.into_iter().fold(Steps::build().label(label), |steps, action| {
+                        steps.step(RegisteredAction(action))
+                    });
+                    thinker.when(RegisteredScorer(scorer), steps)
+                }
+            };
+        }
+
+        Ok(thinker)
+    }
+}
+
+#[ext(name = BehaviorAppExt)]
+pub impl App {
+    /// Makes a scorer available to behavior definitions under `name`.
+    fn register_scorer(&mut self, name: &str, scorer: impl ScorerBuilder + 'static) -> &mut App {
+        self.world_mut()
+            .get_resource_or_insert_with(BehaviorRegistry::default)
+            .register_scorer(name, scorer);
+        self
+    }
+
+    /// Makes an action available to behavior definitions under `name`.
+    fn register_action(&mut self, name: &str, action: impl ActionBuilder + 'static) -> &mut App {
+        self.world_mut()
+            .get_resource_or_insert_with(BehaviorRegistry::default)
+            .register_action(name, action);
+        self
+    }
+}
+
+#[derive(Debug)]
+struct RegisteredScorer(Arc<dyn ScorerBuilder>);
+
+impl ScorerBuilder for RegisteredScorer {
+    fn build(&self, cmd: &mut Commands, scorer: Entity, actor: Entity) {
+        self.0.build(cmd, scorer, actor);
+    }
+
+    fn label(&self) -> Option<&str> {
+        self.0.label()
+    }
+}
+
+#[derive(Debug)]
+struct RegisteredAction(Arc<dyn ActionBuilder>);
+
+impl ActionBuilder for RegisteredAction {
+    fn build(&self, cmd: &mut Commands, action: Entity, actor: Entity) {
+        self.0.build(cmd, action, actor);
+    }
+
+    fn label(&self) -> Option<&str> {
+        self.0.label()
+    }
+}
+
+#[derive(Debug)]
+pub enum BehaviorDefinitionsLoaderError {
+    Io(std::io::Error),
+    Format(ron::error::SpannedError),
+}
+
+impl Display for BehaviorDefinitionsLoaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BehaviorDefinitionsLoaderError::Io(err) => write!(f, "{}", err),
+            BehaviorDefinitionsLoaderError::Format(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for BehaviorDefinitionsLoaderError {}
+
+impl From<std::io::Error> for BehaviorDefinitionsLoaderError {
+    fn from(err: std::io::Error) -> Self {
+        BehaviorDefinitionsLoaderError::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for BehaviorDefinitionsLoaderError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        BehaviorDefinitionsLoaderError::Format(err)
+    }
+}
+
+#[derive(Default)]
+struct BehaviorDefinitionsLoader;
+
+impl AssetLoader for BehaviorDefinitionsLoader {
+    type Asset = BehaviorDefinitions;
+    type Settings = ();
+    type Error = BehaviorDefinitionsLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["behaviors.ron"]
+    }
+}
+
+#[derive(Resource)]
+struct Behaviors(Handle<BehaviorDefinitions>);
+
+fn load_behaviors_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Behaviors(asset_server.load(BEHAVIORS_PATH)));
+}
+
+/// Gives villagers the thinker of their profession once the definitions are loaded, and a new one whenever the
+/// definitions or their profession change
+fn apply_behaviors_system(
+    mut commands: Commands,
+    behaviors: Res<Behaviors>,
+    definitions: Res<Assets<BehaviorDefinitions>>,
+    registry: Res<BehaviorRegistry>,
+    mut asset_events: EventReader<AssetEvent<BehaviorDefinitions>>,
+    villagers: Query<(Entity, Ref<Profession>, Option<&HasThinker>)>,
+    mut release_writer: EventWriter<ReleaseReservation>,
+) {
+    let reloaded = asset_events
+        .read()
+        .any(|event| event.is_loaded_with_dependencies(&behaviors.0) || event.is_modified(&behaviors.0));
+
+    let Some(definitions) = definitions.get(&behaviors.0) else {
+        return;
+    };
+
+    for (villager, profession, has_thinker) in villagers.iter() {
+        // Villagers that were just spawned count as changed
+        if !reloaded && !profession.is_changed() {
+            continue;
+        }
+
+        let Some(definition) = definitions
+            .professions
+            .iter()
+            .find(|definition| definition.name == profession.0)
+        else {
+            error!("{:?} has no profession called {}", villager, profession.0);
+            continue;
+        };
+
+        // A broken definition leaves villagers thinking the way they did before it was changed
+        let thinker = match registry.thinker(definition) {
+            Ok(thinker) => thinker,
+            Err(err) => {
+                error!("Failed to build the {} thinker: {}", definition.name, err);
+                continue;
+            }
+        };
+
+        if let Some(has_thinker) = has_thinker {
+            // Nothing the old thinker was doing is carried over, so give up whatever it was holding on to
+            commands.entity(has_thinker.entity()).despawn_recursive();
+            commands
+                .entity(villager)
+                .remove::<(HasThinker, GatheringTag, GatheringTimer)>();
+            release_writer.send(ReleaseReservation {
+                owner: villager,
+                target: None,
+                reason: ReleaseReason::Failed,
+            });
+        }
+
+        commands.entity(villager).insert(thinker);
+    }
+}
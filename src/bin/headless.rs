@@ -0,0 +1,219 @@
+//! Runs a colony without a window and prints a JSON summary of the simulation.
+//!
+//! ```sh
+//! cargo run --bin headless -- --seed 3 --ticks 3600
+//! ```
+//!
+//! Each tick advances the simulation by `--timestep-ms`, so the summary depends only on the seed, the number of ticks
+//! and the timestep, and runs with the same three print the same summary.
+
+use bevy::app::PluginsState;
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy_ecs_tilemap::prelude::TilePos;
+use bevy_game::agent::Bush;
+use bevy_game::animation::GatheringTag;
+use bevy_game::behavior::BehaviorDefinitions;
+use bevy_game::designation::{DesignationEvent, DesignationKind};
+use bevy_game::jobs::DEFAULT_PRIORITY;
+use bevy_game::states::States::{LoadMenu, Play, Worldgen};
+use bevy_game::stockpile::{spawn_stockpile, Stockpile, StockpileCell};
+use bevy_game::villager::{camp_tiles, Movement, MovementCosts, PathBlocked, PathNotFound};
+use bevy_game::worldgen::{World, WorldSeed};
+use bevy_game::{DeterministicPlugin, SimulationPlugin, SimulationSet};
+use big_brain::prelude::HasThinker;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Worldgen normally finishes within a handful of frames, so this only guards against hanging forever
+const MAX_WORLDGEN_TICKS: u32 = 1000;
+
+struct Options {
+    seed: u64,
+    ticks: u32,
+    timestep: Duration,
+}
+
+impl Options {
+    fn from_args() -> Result<Self, String> {
+        let mut options = Options {
+            seed: WorldSeed::default().0,
+            ticks: 3600,
+            timestep: Duration::from_secs_f64(1.0 / 60.0),
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?;
+            match arg.as_str() {
+                "--seed" => options.seed = value.parse().map_err(|_| format!("invalid seed {}", value))?,
+                "--ticks" => options.ticks = value.parse().map_err(|_| format!("invalid ticks {}", value))?,
+                "--timestep-ms" => {
+                    let millis = value.parse().map_err(|_| format!("invalid timestep {}", value))?;
+                    options.timestep = Duration::from_millis(millis);
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+#[derive(Default, Resource, Serialize)]
+struct Summary {
+    seed: u64,
+    ticks: u32,
+    timestep_seconds: f32,
+    villagers: usize,
+    bushes_designated: usize,
+    bushes_gathered: usize,
+    villager_idle_seconds: f32,
+    path_failures: usize,
+    paths_blocked: usize,
+    items_stockpiled: u32,
+}
+
+fn main() {
+    let options = match Options::from_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("usage: headless [--seed <u64>] [--ticks <u32>] [--timestep-ms <u64>]");
+            std::process::exit(2);
+        }
+    };
+
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        TransformPlugin,
+        HierarchyPlugin,
+        AssetPlugin::default(),
+        ImagePlugin::default(),
+        StatesPlugin,
+        LogPlugin {
+            filter: "bevy_game=warn".into(),
+            ..default()
+        },
+    ))
+    // Advance the clock by exactly one timestep per tick so that runs do not depend on the host
+    .insert_resource(TimeUpdateStrategy::ManualDuration(options.timestep))
+    .insert_resource(WorldSeed(options.seed))
+    .insert_resource(Summary {
+        seed: options.seed,
+        timestep_seconds: options.timestep.as_secs_f32(),
+        ..default()
+    })
+    .add_plugins((SimulationPlugin, DeterministicPlugin))
+    .add_systems(Update, skip_menu.run_if(in_state(LoadMenu)))
+    .add_systems(OnEnter(Play), (designate_all_bushes, designate_stockpile))
+    .add_systems(
+        FixedUpdate,
+        (count_gathered_bushes, count_idle_time, count_path_failures)
+            .after(SimulationSet::Move)
+            .run_if(in_state(Play)),
+    );
+
+    while app.plugins_state() == PluginsState::Adding {
+        bevy::tasks::tick_global_task_pools_on_main_thread();
+    }
+    app.finish();
+    app.cleanup();
+
+    let mut worldgen_ticks = 0;
+    while *app.world().resource::<State<bevy_game::states::States>>() != Play {
+        if worldgen_ticks == MAX_WORLDGEN_TICKS {
+            eprintln!("worldgen did not finish within {} ticks", MAX_WORLDGEN_TICKS);
+            std::process::exit(1);
+        }
+        app.update();
+        worldgen_ticks += 1;
+    }
+
+    for _ in 0..options.ticks {
+        app.update();
+    }
+
+    let world = app.world_mut();
+    let villagers = world.query_filtered::<(), With<HasThinker>>().iter(world).count();
+    let items_stockpiled = world
+        .query::<&StockpileCell>()
+        .iter(world)
+        .filter_map(|cell| cell.contents)
+        .map(|stack| stack.count)
+        .sum();
+    let mut summary = world.resource_mut::<Summary>();
+    summary.ticks = options.ticks;
+    summary.villagers = villagers;
+    summary.items_stockpiled = items_stockpiled;
+
+    println!("{}", serde_json::to_string_pretty(&*summary).unwrap());
+}
+
+/// Waits for the behaviors to load first, so that villagers are given their thinkers on the same step every run
+fn skip_menu(
+    definitions: Res<Assets<BehaviorDefinitions>>,
+    mut next_state: ResMut<NextState<bevy_game::states::States>>,
+) {
+    if !definitions.is_empty() {
+        next_state.set(Worldgen);
+    }
+}
+
+/// There is no player to designate work, so every bush is up for gathering
+fn designate_all_bushes(
+    bushes: Query<Entity, With<Bush>>,
+    mut designation_writer: EventWriter<DesignationEvent>,
+    mut summary: ResMut<Summary>,
+) {
+    designation_writer.send(DesignationEvent {
+        kind: DesignationKind::Gather,
+        priority: DEFAULT_PRIORITY,
+        entities: bushes.iter().collect(),
+        rect: Rect::default(),
+    });
+
+    summary.bushes_designated = bushes.iter().count();
+}
+
+/// A stockpile beside where the villagers start, so that there is somewhere to haul the harvest
+fn designate_stockpile(mut commands: Commands, world: Res<World>, costs: Res<MovementCosts>) {
+    // A 2x2 zone by the camp, past the tiles the villagers started on and their beds stand on
+    let tiles = camp_tiles(&world, &costs, 64);
+    let free = &tiles[tiles.len().min(12)..];
+    let corner = free.iter().find(|tilepos| {
+        [(1, 0), (0, 1), (1, 1)]
+            .iter()
+            .all(|&(dx, dy)| free.contains(&TilePos::new(tilepos.x + dx, tilepos.y + dy)))
+    });
+
+    if let Some(&min) = corner {
+        let stockpile = Stockpile::new(min, TilePos::new(min.x + 1, min.y + 1));
+        spawn_stockpile(&mut commands, stockpile, |_| None);
+    }
+}
+
+fn count_gathered_bushes(mut removed: RemovedComponents<Bush>, mut summary: ResMut<Summary>) {
+    summary.bushes_gathered += removed.read().count();
+}
+
+fn count_idle_time(
+    time: Res<Time>,
+    villagers: Query<&Movement, (With<HasThinker>, Without<GatheringTag>)>,
+    mut summary: ResMut<Summary>,
+) {
+    let idle = villagers.iter().filter(|movement| movement.target().is_none()).count();
+    summary.villager_idle_seconds += idle as f32 * time.delta_seconds();
+}
+
+fn count_path_failures(
+    mut not_found: EventReader<PathNotFound>,
+    mut blocked: EventReader<PathBlocked>,
+    mut summary: ResMut<Summary>,
+) {
+    summary.path_failures += not_found.read().count();
+    summary.paths_blocked += blocked.read().count();
+}
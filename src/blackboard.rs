@@ -1,106 +1,410 @@
-/// A `Blackboard` is a storage component designed for AI agents to read and store arbitrary data.
-/// It utilizes a JSON-like structure to allow flexible and dynamic data management.
+use crate::states::States::Play;
+use crate::SimulationSet;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::TilePos;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::marker::PhantomData;
+
+pub struct BlackboardPlugin;
+
+impl Plugin for BlackboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ColonyBlackboard>().add_systems(
+            FixedUpdate,
+            expire_blackboards_system
+                .in_set(SimulationSet::Prepare)
+                .run_if(in_state(Play)),
+        );
+    }
+}
+
+/// The name of a `Blackboard` entry along with the type of value it holds.
 ///
-/// The `Blackboard` struct wraps a `serde_json::Value` which can hold any valid JSON data.
-/// This makes it suitable for storing a wide variety of data types and structures that an AI agent
-/// might need to use or modify during its operation.
+/// Keys are meant to be declared once as constants, next to the systems that use them.
 ///
 /// # Examples
 ///
 /// ```
-/// use bevy_game::blackboard::Blackboard;
+/// use bevy_game::blackboard::BlackboardKey;
+///
+/// const HUNGER: BlackboardKey<f32> = BlackboardKey::new("hunger");
+/// assert_eq!(HUNGER.name(), "hunger");
+/// ```
+pub struct BlackboardKey<T> {
+    name: &'static str,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> BlackboardKey<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            marker: PhantomData,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T> Clone for BlackboardKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for BlackboardKey<T> {}
+
+impl<T> Debug for BlackboardKey<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("BlackboardKey").field(&self.name).finish()
+    }
+}
+
+/// A value stored on a `Blackboard`.
+///
+/// Entities and tile positions are first-class values, rather than numbers, so that entities keep their generation
+/// and can be remapped when a colony is loaded.
+#[derive(Clone, Debug, Deserialize, PartialEq, Reflect, Serialize)]
+pub enum BlackboardValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    Text(String),
+    Entity(Entity),
+    TilePos(TilePos),
+}
+
+/// A type that can be stored on a `Blackboard`
+pub trait BlackboardType: Sized {
+    /// The name of the type, for error messages
+    const NAME: &'static str;
+
+    fn into_value(self) -> BlackboardValue;
+
+    /// Returns the value as this type, or `None` if it holds another type.
+    fn from_value(value: &BlackboardValue) -> Option<Self>;
+}
+
+macro_rules! blackboard_type {
+    ($type:ty, $variant:ident) => {
+        impl BlackboardType for $type {
+            const NAME: &'static str = stringify!($type);
+
+            fn into_value(self) -> BlackboardValue {
+                BlackboardValue::$variant(self)
+            }
+
+            fn from_value(value: &BlackboardValue) -> Option<Self> {
+                match value {
+                    BlackboardValue::$variant(value) => Some(value.clone()),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+blackboard_type!(bool, Bool);
+blackboard_type!(i64, Int);
+blackboard_type!(f32, Float);
+blackboard_type!(String, Text);
+blackboard_type!(Entity, Entity);
+blackboard_type!(TilePos, TilePos);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlackboardError {
+    /// Nothing is stored under the key
+    Missing(&'static str),
+    /// A value of another type is stored under the key
+    WrongType { key: &'static str, expected: &'static str },
+}
+
+impl Display for BlackboardError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlackboardError::Missing(key) => write!(f, "nothing is stored under {}", key),
+            BlackboardError::WrongType { key, expected } => {
+                write!(f, "the value stored under {} is not a {}", key, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BlackboardError {}
+
+#[derive(Clone, Debug, Deserialize, Reflect, Serialize)]
+struct Entry {
+    /// `None` once the value was removed, so that the removal can still be noticed
+    value: Option<BlackboardValue>,
+    /// The revision the value was last inserted or removed at
+    changed: u64,
+    /// How many seconds are left before the value expires, counted down rather than stored as a time so that it
+    /// survives being saved and loaded
+    #[serde(default)]
+    ttl: Option<f32>,
+}
+
+/// A `Blackboard` is a storage component for AI agents to read and store typed values under `BlackboardKey`s.
+///
+/// Every insert and removal bumps the blackboard's revision, so a system can tell which keys changed since it last
+/// looked by remembering the revision it saw.
+///
+/// # Examples
+///
+/// ```
+/// use bevy_game::blackboard::{Blackboard, BlackboardError, BlackboardKey};
+///
+/// const HEALTH: BlackboardKey<i64> = BlackboardKey::new("health");
+/// const NAME: BlackboardKey<String> = BlackboardKey::new("name");
 ///
 /// let mut blackboard = Blackboard::new();
-/// blackboard.insert("health", serde_json::json!(100));
-/// blackboard.insert("name", serde_json::json!("Agent Smith"));
+/// blackboard.insert(HEALTH, 100);
+/// let seen = blackboard.revision();
 ///
-/// let health = blackboard.get("health");
-/// println!("Agent health: {}", health);
+/// blackboard.insert(NAME, "Agent Smith".to_string());
 ///
-/// let name = blackboard.get("name");
-/// println!("Agent name: {}", name);
+/// assert_eq!(blackboard.get(HEALTH), Some(100));
+/// assert_eq!(
+///     blackboard.try_get(BlackboardKey::<bool>::new("health")),
+///     Err(BlackboardError::WrongType {
+///         key: "health",
+///         expected: "bool"
+///     })
+/// );
+/// assert!(!blackboard.changed_since(HEALTH, seen));
+/// assert!(blackboard.changed_since(NAME, seen));
 /// ```
-#[derive(bevy::prelude::Component, Debug)]
-pub struct Blackboard(serde_json::Value);
+#[derive(Clone, Component, Debug, Default, Deserialize, Reflect, Serialize)]
+#[reflect(Component)]
+pub struct Blackboard {
+    entries: HashMap<String, Entry>,
+    revision: u64,
+}
 
 impl Blackboard {
     /// Creates a new, empty `Blackboard`.
     ///
-    /// This initializes the `Blackboard` with an empty JSON object, ready to store key-value pairs.
-    ///
     /// # Examples
     ///
     /// ```
     /// let blackboard = bevy_game::blackboard::Blackboard::new();
     /// ```
     pub fn new() -> Self {
-        Self(serde_json::json!({}))
+        Self::default()
     }
 
-    /// Gets a reference to the value corresponding to the key.
+    /// Returns the value stored under the key, or `None` if it is missing or of another type.
     ///
-    /// If the key does not exist, this function will panic. It is recommended to ensure that the
-    /// key exists before calling this method to avoid runtime panics.
+    /// # Examples
     ///
-    /// # Arguments
+    /// ```
+    /// use bevy_game::blackboard::{Blackboard, BlackboardKey};
     ///
-    /// * `key` - A string slice that holds the name of the key.
+    /// const KEY: BlackboardKey<bool> = BlackboardKey::new("key");
     ///
-    /// # Examples
+    /// let mut blackboard = Blackboard::new();
+    /// assert_eq!(blackboard.get(KEY), None);
     ///
+    /// blackboard.insert(KEY, true);
+    /// assert_eq!(blackboard.get(KEY), Some(true));
     /// ```
-    /// let mut blackboard = bevy_game::blackboard::Blackboard::new();
-    /// blackboard.insert("key", serde_json::json!("value"));
-    /// assert_eq!(blackboard.get("key"), &serde_json::json!("value"));
-    /// ```
-    pub fn get<I: serde_json::value::Index>(&self, key: I) -> &serde_json::Value {
-        self.0.get(key).unwrap()
+    pub fn get<T: BlackboardType>(&self, key: BlackboardKey<T>) -> Option<T> {
+        self.try_get(key).ok()
+    }
+
+    /// Returns the value stored under the key, or why it could not be.
+    pub fn try_get<T: BlackboardType>(&self, key: BlackboardKey<T>) -> Result<T, BlackboardError> {
+        let value = self
+            .entries
+            .get(key.name)
+            .and_then(|entry| entry.value.as_ref())
+            .ok_or(BlackboardError::Missing(key.name))?;
+
+        T::from_value(value).ok_or(BlackboardError::WrongType {
+            key: key.name,
+            expected: T::NAME,
+        })
+    }
+
+    pub fn contains<T: BlackboardType>(&self, key: BlackboardKey<T>) -> bool {
+        self.get(key).is_some()
     }
 
-    /// Sets a key-value pair in the `Blackboard`.
+    /// Stores a value under the key, replacing whatever was stored there.
+    pub fn insert<T: BlackboardType>(&mut self, key: BlackboardKey<T>, value: T) {
+        self.store(key, value, None);
+    }
+
+    /// Stores a value under the key that is removed once `seconds` have passed, for facts that go stale.
     ///
-    /// This method allows you to store data in the `Blackboard` by associating a key with a value.
-    /// The key must be a string, and the value can be any valid JSON value.
+    /// # Examples
     ///
-    /// # Arguments
+    /// ```
+    /// use bevy_game::blackboard::{Blackboard, BlackboardKey};
     ///
-    /// * `key` - A string slice that holds the name of the key.
-    /// * `value` - A `serde_json::Value` that holds the value to be associated with the key.
+    /// const SEEN_WOLF: BlackboardKey<bool> = BlackboardKey::new("seen_wolf");
     ///
-    /// # Examples
+    /// let mut blackboard = Blackboard::new();
+    /// blackboard.insert_for(SEEN_WOLF, true, 2.0);
     ///
+    /// assert_eq!(blackboard.expire(1.5), 0);
+    /// assert!(blackboard.contains(SEEN_WOLF));
+    /// assert_eq!(blackboard.expire(1.5), 1);
+    /// assert!(!blackboard.contains(SEEN_WOLF));
     /// ```
-    /// let mut blackboard = bevy_game::blackboard::Blackboard::new();
-    /// blackboard.insert("key", serde_json::json!("value"));
-    /// assert_eq!(blackboard.get("key"), &serde_json::json!("value"));
-    /// ```
-    pub fn insert(&mut self, key: &str, value: serde_json::Value) {
-        self.0.as_object_mut().unwrap().insert(key.to_string(), value);
+    pub fn insert_for<T: BlackboardType>(&mut self, key: BlackboardKey<T>, value: T, seconds: f32) {
+        self.store(key, value, Some(seconds));
     }
 
-    /// Removes a key-value pair from the `Blackboard`.
-    ///
-    /// This method allows you to delete data from the `Blackboard` by specifying the key.
-    /// If the key does not exist, this method does nothing.
-    ///
-    /// # Arguments
-    ///
-    /// * `key` - A string slice that holds the name of the key.
+    fn store<T: BlackboardType>(&mut self, key: BlackboardKey<T>, value: T, ttl: Option<f32>) {
+        self.revision += 1;
+        self.entries.insert(
+            key.name.to_string(),
+            Entry {
+                value: Some(value.into_value()),
+                changed: self.revision,
+                ttl,
+            },
+        );
+    }
+
+    /// Removes the value stored under the key, returning it if it was of the key's type.
     ///
     /// # Examples
     ///
     /// ```
-    /// let mut blackboard = bevy_game::blackboard::Blackboard::new();
-    /// blackboard.insert("key", serde_json::json!("value"));
-    /// blackboard.remove("key");
+    /// use bevy_game::blackboard::{Blackboard, BlackboardKey};
+    ///
+    /// const KEY: BlackboardKey<String> = BlackboardKey::new("key");
+    ///
+    /// let mut blackboard = Blackboard::new();
+    /// blackboard.insert(KEY, "value".to_string());
+    /// assert_eq!(blackboard.remove(KEY), Some("value".to_string()));
+    /// assert!(!blackboard.contains(KEY));
     /// ```
-    pub fn remove(&mut self, key: &str) {
-        self.0.as_object_mut().unwrap().remove(key);
+    pub fn remove<T: BlackboardType>(&mut self, key: BlackboardKey<T>) -> Option<T> {
+        let entry = self.entries.get_mut(key.name)?;
+        let value = entry.value.take()?;
+
+        self.revision += 1;
+        entry.changed = self.revision;
+        entry.ttl = None;
+        T::from_value(&value)
+    }
+
+    /// Counts down the time left on expiring values by `seconds`, removing those that ran out.
+    ///
+    /// Returns how many values expired.
+    pub fn expire(&mut self, seconds: f32) -> usize {
+        let mut expired = 0;
+
+        for entry in self.entries.values_mut() {
+            let Some(ttl) = entry.ttl.as_mut() else {
+                continue;
+            };
+
+            *ttl -= seconds;
+            if *ttl <= 0.0 {
+                self.revision += 1;
+                entry.value = None;
+                entry.changed = self.revision;
+                entry.ttl = None;
+                expired += 1;
+            }
+        }
+
+        expired
+    }
+
+    /// Returns the current revision, which goes up with every insert and removal.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Returns true if the key was inserted or removed after `revision`.
+    pub fn changed_since<T: BlackboardType>(&self, key: BlackboardKey<T>, revision: u64) -> bool {
+        self.entries.get(key.name).is_some_and(|entry| entry.changed > revision)
+    }
+
+    /// Replaces every stored entity with the one `map` returns for it, removing those it returns `None` for.
+    ///
+    /// This is for entities that were recreated, such as when a colony is loaded, so it does not count as a change.
+    pub fn map_entities(&mut self, mut map: impl FnMut(Entity) -> Option<Entity>) {
+        for entry in self.entries.values_mut() {
+            if let Some(BlackboardValue::Entity(entity)) = entry.value {
+                entry.value = map(entity).map(BlackboardValue::Entity);
+            }
+        }
     }
 }
 
-impl Default for Blackboard {
-    fn default() -> Self {
-        Self::new()
+/// Facts shared by the whole colony, such as where berries were last found or whether the stockpiles are full
+#[derive(Default, Reflect, Resource)]
+#[reflect(Resource)]
+pub struct ColonyBlackboard(pub Blackboard);
+
+/// Marks an entity whose `Blackboard` holds the facts shared by a group of agents
+#[derive(Component, Default)]
+pub struct Squad;
+
+/// Puts an agent in the squad entity it points at, so that it can look up facts on the squad's blackboard
+#[derive(Clone, Component, Copy, Debug)]
+pub struct InSquad(pub Entity);
+
+/// Where a blackboard value was found
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BlackboardScope {
+    Agent,
+    Squad,
+    Colony,
+}
+
+/// Looks values up on an agent's own blackboard first, then on its squad's and finally on the colony's
+#[derive(SystemParam)]
+pub struct Blackboards<'w, 's> {
+    agents: Query<'w, 's, (Option<&'static Blackboard>, Option<&'static InSquad>), Without<Squad>>,
+    squads: Query<'w, 's, &'static Blackboard, With<Squad>>,
+    colony: Res<'w, ColonyBlackboard>,
+}
+
+impl Blackboards<'_, '_> {
+    /// Returns the value stored under the key in the closest scope that has one.
+    pub fn get<T: BlackboardType>(&self, agent: Entity, key: BlackboardKey<T>) -> Option<T> {
+        self.lookup(agent, key).map(|(_, value)| value)
+    }
+
+    /// Returns the value stored under the key in the closest scope that has one, along with that scope.
+    pub fn lookup<T: BlackboardType>(&self, agent: Entity, key: BlackboardKey<T>) -> Option<(BlackboardScope, T)> {
+        let (own, squad) = self.agents.get(agent).unwrap_or((None, None));
+        let squad = squad.and_then(|squad| self.squads.get(squad.0).ok());
+
+        own.and_then(|blackboard| blackboard.get(key))
+            .map(|value| (BlackboardScope::Agent, value))
+            .or_else(|| squad?.get(key).map(|value| (BlackboardScope::Squad, value)))
+            .or_else(|| self.colony.0.get(key).map(|value| (BlackboardScope::Colony, value)))
+    }
+}
+
+fn expire_blackboards_system(
+    time: Res<Time>,
+    mut colony: ResMut<ColonyBlackboard>,
+    mut blackboards: Query<&mut Blackboard>,
+) {
+    let seconds = time.delta_seconds();
+
+    // Counting down is not a change, only values running out are
+    if colony.bypass_change_detection().0.expire(seconds) > 0 {
+        colony.set_changed();
+    }
+
+    for mut blackboard in blackboards.iter_mut() {
+        if blackboard.bypass_change_detection().expire(seconds) > 0 {
+            blackboard.set_changed();
+        }
     }
 }
@@ -0,0 +1,278 @@
+use crate::agent::{Bush, Rock, Tree};
+use crate::jobs::{DEFAULT_PRIORITY, HIGHEST_PRIORITY, LOWEST_PRIORITY};
+use crate::reservations::{
+    ReleaseReason, ReleaseReservation, RemoveReservation, Reservable, ReservationLedger, Reserved,
+};
+use crate::states::States::Play;
+use crate::SimulationSet;
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::TilePos;
+
+pub struct DesignationPlugin;
+
+impl Plugin for DesignationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DesignationMode>()
+            .init_resource::<DesignationPriority>()
+            .add_event::<DesignationEvent>()
+            .add_systems(
+                FixedUpdate,
+                (designate_work_system, cancel_designation_system)
+                    .chain()
+                    .in_set(SimulationSet::Settle)
+                    .run_if(in_state(Play)),
+            );
+    }
+}
+
+/// Hotkeys and a toolbar for picking the `DesignationMode` and `DesignationPriority`
+pub struct DesignationToolbarPlugin;
+
+impl Plugin for DesignationToolbarPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(Play), setup_toolbar).add_systems(
+            Update,
+            (
+                designation_hotkey_system,
+                toolbar_button_system,
+                toolbar_style_system,
+                priority_label_system,
+            )
+                .chain()
+                .run_if(in_state(Play)),
+        );
+    }
+}
+
+/// What dragging the marquee over the map does
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DesignationKind {
+    /// Mark bushes to be gathered
+    #[default]
+    Gather,
+    /// Mark trees to be chopped down
+    Chop,
+    /// Mark rocks to be mined
+    Mine,
+    /// Withdraw any designation, releasing reservations on it
+    Cancel,
+    /// Turn the area into a stockpile zone
+    Zone,
+}
+
+impl DesignationKind {
+    const ALL: [(DesignationKind, KeyCode, &'static str); 5] = [
+        (DesignationKind::Gather, KeyCode::KeyG, "[G] Gather"),
+        (DesignationKind::Chop, KeyCode::KeyC, "[C] Chop"),
+        (DesignationKind::Mine, KeyCode::KeyM, "[M] Mine"),
+        (DesignationKind::Cancel, KeyCode::KeyX, "[X] Cancel"),
+        (DesignationKind::Zone, KeyCode::KeyZ, "[Z] Zone"),
+    ];
+}
+
+/// The designation the marquee currently applies
+#[derive(Default, Resource)]
+pub struct DesignationMode(pub DesignationKind);
+
+/// The priority, from `HIGHEST_PRIORITY` to `LOWEST_PRIORITY`, the marquee designates targets at
+#[derive(Resource)]
+pub struct DesignationPriority(pub u8);
+
+impl Default for DesignationPriority {
+    fn default() -> Self {
+        Self(DEFAULT_PRIORITY)
+    }
+}
+
+/// Marks a designated target with the priority the job posted for it gets
+#[derive(Clone, Copy, Component, Debug)]
+pub struct Designated {
+    pub priority: u8,
+}
+
+/// Published when the player releases the marquee, with everything it was dragged over
+#[derive(Event)]
+pub struct DesignationEvent {
+    pub kind: DesignationKind,
+    /// The priority targets are designated at
+    pub priority: u8,
+    pub entities: Vec<Entity>,
+    /// The area covered by the marquee, in world space
+    pub rect: Rect,
+}
+
+/// Makes gathering, chopping and mining targets available to work on
+fn designate_work_system(
+    mut commands: Commands,
+    mut events: EventReader<DesignationEvent>,
+    targets: Query<(Has<Bush>, Has<Tree>, Has<Rock>), (Without<Reservable>, Without<Reserved>)>,
+) {
+    for event in events.read() {
+        for &entity in &event.entities {
+            let Ok((bush, tree, rock)) = targets.get(entity) else {
+                continue;
+            };
+
+            let designated = match event.kind {
+                DesignationKind::Gather => bush,
+                DesignationKind::Chop => tree,
+                DesignationKind::Mine => rock,
+                DesignationKind::Cancel | DesignationKind::Zone => false,
+            };
+
+            if designated {
+                commands.entity(entity).insert((
+                    Reservable,
+                    Designated {
+                        priority: event.priority,
+                    },
+                ));
+            }
+        }
+    }
+}
+
+/// Withdraws designations, taking reservations away from whoever holds them
+fn cancel_designation_system(
+    mut commands: Commands,
+    mut events: EventReader<DesignationEvent>,
+    ledger: Res<ReservationLedger>,
+    targets: Query<Option<&TilePos>, Or<(With<Reservable>, With<Reserved>)>>,
+    mut remove_reservation_writer: EventWriter<RemoveReservation>,
+    mut release_writer: EventWriter<ReleaseReservation>,
+) {
+    for event in events.read().filter(|event| event.kind == DesignationKind::Cancel) {
+        for &entity in &event.entities {
+            let Ok(tilepos) = targets.get(entity) else {
+                continue;
+            };
+
+            commands.entity(entity).remove::<(Reservable, Reserved, Designated)>();
+
+            for owner in ledger.who_reserved(entity) {
+                release_writer.send(ReleaseReservation {
+                    owner,
+                    target: Some(entity.into()),
+                    reason: ReleaseReason::Cancelled,
+                });
+            }
+
+            if let Some(&tilepos) = tilepos {
+                remove_reservation_writer.send(RemoveReservation { tilepos });
+            }
+        }
+    }
+}
+
+const PRIORITY_KEYS: [KeyCode; 4] = [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4];
+
+fn designation_hotkey_system(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut mode: ResMut<DesignationMode>,
+    mut priority: ResMut<DesignationPriority>,
+) {
+    for (kind, key, _) in DesignationKind::ALL {
+        if keys.just_pressed(key) {
+            mode.0 = kind;
+        }
+    }
+
+    for (priority_level, key) in (HIGHEST_PRIORITY..=LOWEST_PRIORITY).zip(PRIORITY_KEYS) {
+        if keys.just_pressed(key) {
+            priority.0 = priority_level;
+        }
+    }
+}
+
+#[derive(Component)]
+struct ToolbarButton(DesignationKind);
+
+/// The toolbar text showing the `DesignationPriority`
+#[derive(Component)]
+struct PriorityLabel;
+
+fn setup_toolbar(mut commands: Commands) {
+    commands
+        .spawn((
+            Name::new("Designation Toolbar"),
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(8.0),
+                    left: Val::Px(8.0),
+                    column_gap: Val::Px(4.0),
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|toolbar| {
+            for (kind, _, label) in DesignationKind::ALL {
+                toolbar
+                    .spawn((
+                        ToolbarButton(kind),
+                        ButtonBundle {
+                            style: Style {
+                                padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                    ))
+                    .with_children(|button| {
+                        button.spawn(TextBundle::from_section(
+                            label,
+                            TextStyle {
+                                font_size: 16.0,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        ));
+                    });
+            }
+
+            toolbar.spawn((
+                PriorityLabel,
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    align_self: AlignSelf::Center,
+                    ..default()
+                }),
+            ));
+        });
+}
+
+fn toolbar_button_system(
+    buttons: Query<(&Interaction, &ToolbarButton), Changed<Interaction>>,
+    mut mode: ResMut<DesignationMode>,
+) {
+    for (interaction, button) in buttons.iter() {
+        if *interaction == Interaction::Pressed {
+            mode.0 = button.0;
+        }
+    }
+}
+
+/// Highlights the button of the active mode
+fn toolbar_style_system(mode: Res<DesignationMode>, mut buttons: Query<(&ToolbarButton, &mut BackgroundColor)>) {
+    for (button, mut background) in buttons.iter_mut() {
+        *background = if button.0 == mode.0 {
+            BackgroundColor(Color::srgb_u8(38, 139, 210)) // Solarized Blue
+        } else {
+            BackgroundColor(Color::srgb_u8(7, 54, 66)) // Solarized Base02
+        };
+    }
+}
+
+fn priority_label_system(priority: Res<DesignationPriority>, mut labels: Query<&mut Text, With<PriorityLabel>>) {
+    for mut text in labels.iter_mut() {
+        text.sections[0].value = format!("[1-4] Priority {}", priority.0);
+    }
+}
@@ -0,0 +1,401 @@
+use crate::villager::{DiagonalMovement, MovementCosts};
+use crate::worldgen::{World, TILEMAP_SIZE};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::helpers::square_grid::neighbors::Neighbors;
+use bevy_ecs_tilemap::prelude::TilePos;
+use pathfinding::prelude::{astar, dijkstra_all};
+use std::collections::{HashMap, HashSet};
+
+/// Width and height, in tiles, of the clusters the map is divided into
+pub const CLUSTER_SIZE: u32 = 16;
+
+/// Walkable stretches of a cluster border longer than this get an entrance at each end instead of one in the middle
+const MAX_SINGLE_ENTRANCE_WIDTH: u32 = 6;
+
+type Cluster = (u32, u32);
+
+/// An abstract graph over the `World` for hierarchical pathfinding (HPA*).
+///
+/// The map is divided into clusters of `CLUSTER_SIZE` tiles. Wherever a cluster border can be crossed an entrance is
+/// placed, and the entrances of each cluster are linked by the cost of the cheapest route between them that stays
+/// inside the cluster. A search then only has to explore the entrances, and the few clusters it passes through are
+/// refined into tiles afterwards.
+///
+/// The paths found are within a few percent of the cheapest, but the search is much faster than `find_path` over long
+/// distances. Crossing borders diagonally is not considered when planning, although refined paths do step
+/// diagonally inside clusters when the `MovementCosts` allow it.
+///
+/// # Examples
+///
+/// ```
+/// use bevy_ecs_tilemap::prelude::TilePos;
+/// use bevy_game::hierarchy::HierarchicalGraph;
+/// use bevy_game::villager::{MovementCosts, EMPTY_TILE_ID};
+/// use bevy_game::worldgen::{World, TILEMAP_SIZE};
+/// use grid_2d::{Coord, Grid, Size};
+///
+/// let mut world = World {
+///     tiles: Grid::new_copy(Size::new(TILEMAP_SIZE.x, TILEMAP_SIZE.y), 17),
+/// };
+/// let mut graph = HierarchicalGraph::new(&world, &MovementCosts::default());
+/// let start = TilePos { x: 0, y: 0 };
+/// let goal = TilePos { x: 200, y: 0 };
+///
+/// assert_eq!(graph.find_path(start, goal).unwrap().len(), 201);
+///
+/// // Wall off the goal and let the graph catch up with the change
+/// for y in 0..TILEMAP_SIZE.y {
+///     *world.tiles.get_checked_mut(Coord::new(100, y as i32)) = EMPTY_TILE_ID;
+/// }
+/// graph.update(&world);
+///
+/// assert_eq!(graph.find_path(start, goal), None);
+/// ```
+#[derive(Clone, Resource)]
+pub struct HierarchicalGraph {
+    /// The terrain the graph describes, compared against the `World` to find the tiles that changed
+    world: World,
+    costs: MovementCosts,
+    /// Pairs of entrances facing each other across the border of two neighbouring clusters, lowest cluster first
+    borders: HashMap<(Cluster, Cluster), Vec<(TilePos, TilePos)>>,
+    /// The cheapest routes between the entrances of each cluster that stay inside the cluster
+    edges: HashMap<Cluster, HashMap<TilePos, Vec<(TilePos, u32)>>>,
+}
+
+impl HierarchicalGraph {
+    /// Builds the abstract graph for the whole map.
+    pub fn new(world: &World, costs: &MovementCosts) -> Self {
+        let mut graph = Self {
+            world: world.clone(),
+            costs: costs.clone(),
+            borders: HashMap::new(),
+            edges: HashMap::new(),
+        };
+
+        let clusters = graph.clusters();
+        for &cluster in &clusters {
+            for neighbor in [(cluster.0 + 1, cluster.1), (cluster.0, cluster.1 + 1)] {
+                graph.build_border(cluster, neighbor);
+            }
+        }
+
+        for &cluster in &clusters {
+            graph.build_edges(cluster);
+        }
+
+        graph
+    }
+
+    /// Brings the graph up to date with `world`, only rebuilding the clusters around tiles that changed.
+    ///
+    /// Returns how many tiles had changed.
+    pub fn update(&mut self, world: &World) -> usize {
+        let changed: Vec<TilePos> = world
+            .tiles
+            .enumerate()
+            .zip(self.world.tiles.iter())
+            .filter(|((_, new), old)| new != old)
+            .map(|((coord, _), _)| TilePos::new(coord.x as u32, coord.y as u32))
+            .collect();
+
+        if changed.is_empty() {
+            return 0;
+        }
+
+        self.world = world.clone();
+
+        let dirty: HashSet<Cluster> = changed.iter().map(cluster_of).collect();
+        let mut stale_edges = dirty.clone();
+
+        for &cluster in &dirty {
+            for neighbor in self.neighboring_clusters(cluster) {
+                self.build_border(cluster, neighbor);
+                stale_edges.insert(neighbor);
+            }
+        }
+
+        for cluster in stale_edges {
+            self.build_edges(cluster);
+        }
+
+        changed.len()
+    }
+
+    /// Finds a path from `start` to `goal`, including both ends, by planning across clusters and then refining.
+    pub fn find_path(&self, start: TilePos, goal: TilePos) -> Option<Vec<TilePos>> {
+        let start_cluster = cluster_of(&start);
+        let goal_cluster = cluster_of(&goal);
+
+        if start_cluster == goal_cluster {
+            if let Some(path) = self.local_path(start, goal) {
+                return Some(path);
+            }
+        }
+
+        // Temporarily connect the ends to the entrances of their clusters, in order so that routes that cost the same
+        // are settled the same way every run
+        let mut entrances_from_start: Vec<(TilePos, u32)> = self.local_costs_from(start).into_iter().collect();
+        entrances_from_start.sort_by_key(|(tilepos, _)| (tilepos.y, tilepos.x));
+        let entrances_to_goal = self.local_costs_to(goal);
+
+        let (plan, _) = astar(
+            &start,
+            |&current| {
+                let mut successors = self.successors(current);
+                if current == start {
+                    successors.extend(entrances_from_start.iter().copied());
+                }
+                if let Some(&cost) = entrances_to_goal.get(&current) {
+                    successors.push((goal, cost));
+                }
+                successors
+            },
+            |&current| self.costs.heuristic(current, goal),
+            |&current| current == goal,
+        )?;
+
+        let mut path = vec![start];
+        for step in plan.windows(2) {
+            if cluster_of(&step[0]) == cluster_of(&step[1]) {
+                path.extend(self.local_path(step[0], step[1])?.into_iter().skip(1));
+            } else {
+                // Entrances on either side of a border are next to each other
+                path.push(step[1]);
+            }
+        }
+
+        Some(path)
+    }
+
+    /// The terrain the graph was last built or updated from.
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn costs(&self) -> &MovementCosts {
+        &self.costs
+    }
+
+    /// Returns the number of entrances in the abstract graph.
+    pub fn entrance_count(&self) -> usize {
+        self.edges.values().map(HashMap::len).sum()
+    }
+
+    fn clusters(&self) -> Vec<Cluster> {
+        let (width, height) = cluster_counts();
+        (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).collect()
+    }
+
+    fn neighboring_clusters(&self, cluster: Cluster) -> Vec<Cluster> {
+        let (width, height) = cluster_counts();
+        let (x, y) = cluster;
+
+        [
+            x.checked_sub(1).map(|x| (x, y)),
+            y.checked_sub(1).map(|y| (x, y)),
+            (x + 1 < width).then_some((x + 1, y)),
+            (y + 1 < height).then_some((x, y + 1)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    fn walkable(&self, tilepos: TilePos) -> bool {
+        self.world
+            .value(&tilepos)
+            .and_then(|value| self.costs.cost(value))
+            .is_some()
+    }
+
+    /// Places entrances along the border between two orthogonally neighbouring clusters
+    fn build_border(&mut self, a: Cluster, b: Cluster) {
+        let (low, high) = if a < b { (a, b) } else { (b, a) };
+        let (width, height) = cluster_counts();
+        if high.0 >= width || high.1 >= height {
+            return;
+        }
+
+        // The pairs of tiles facing each other across the border, in order along it
+        let facing: Vec<(TilePos, TilePos)> = if low.1 == high.1 {
+            let x = high.0 * CLUSTER_SIZE;
+            let (min, max) = cluster_bounds(high);
+            (min.y..=max.y)
+                .map(|y| (TilePos::new(x - 1, y), TilePos::new(x, y)))
+                .collect()
+        } else {
+            let y = high.1 * CLUSTER_SIZE;
+            let (min, max) = cluster_bounds(high);
+            (min.x..=max.x)
+                .map(|x| (TilePos::new(x, y - 1), TilePos::new(x, y)))
+                .collect()
+        };
+
+        let mut entrances = vec![];
+        let mut run: Vec<(TilePos, TilePos)> = vec![];
+
+        for pair in facing.into_iter().map(Some).chain([None]) {
+            if let Some(pair) = pair.filter(|&(low, high)| self.walkable(low) && self.walkable(high)) {
+                run.push(pair);
+                continue;
+            }
+
+            if run.len() as u32 > MAX_SINGLE_ENTRANCE_WIDTH {
+                entrances.push(run[0]);
+                entrances.push(run[run.len() - 1]);
+            } else if !run.is_empty() {
+                entrances.push(run[run.len() / 2]);
+            }
+            run.clear();
+        }
+
+        self.borders.insert((low, high), entrances);
+    }
+
+    /// Links every pair of entrances in a cluster that can reach each other without leaving it
+    fn build_edges(&mut self, cluster: Cluster) {
+        let entrances = self.entrances(cluster);
+        let mut edges = HashMap::new();
+
+        for &entrance in &entrances {
+            let reachable = self.local_costs_from(entrance);
+            let links = entrances
+                .iter()
+                .filter_map(|other| reachable.get(other).map(|&cost| (*other, cost)))
+                .collect();
+            edges.insert(entrance, links);
+        }
+
+        self.edges.insert(cluster, edges);
+    }
+
+    fn entrances(&self, cluster: Cluster) -> Vec<TilePos> {
+        let mut entrances = vec![];
+
+        for neighbor in self.neighboring_clusters(cluster) {
+            let key = if cluster < neighbor {
+                (cluster, neighbor)
+            } else {
+                (neighbor, cluster)
+            };
+
+            for &(low, high) in self.borders.get(&key).into_iter().flatten() {
+                entrances.push(if cluster == key.0 { low } else { high });
+            }
+        }
+
+        entrances.sort_by_key(|tilepos| (tilepos.y, tilepos.x));
+        entrances.dedup();
+        entrances
+    }
+
+    /// The abstract edges leaving an entrance, both across its border and through its cluster
+    fn successors(&self, entrance: TilePos) -> Vec<(TilePos, u32)> {
+        let cluster = cluster_of(&entrance);
+        let mut successors = self
+            .edges
+            .get(&cluster)
+            .and_then(|edges| edges.get(&entrance))
+            .cloned()
+            .unwrap_or_default();
+
+        for neighbor in self.neighboring_clusters(cluster) {
+            let key = if cluster < neighbor {
+                (cluster, neighbor)
+            } else {
+                (neighbor, cluster)
+            };
+
+            for &(low, high) in self.borders.get(&key).into_iter().flatten() {
+                let (from, to) = if cluster == key.0 { (low, high) } else { (high, low) };
+                if from == entrance {
+                    if let Some(cost) = self.costs.step_cost(&self.world, from, to) {
+                        successors.push((to, cost));
+                    }
+                }
+            }
+        }
+
+        successors
+    }
+
+    /// The tiles next to `tilepos` that are in the same cluster
+    fn local_neighbors(&self, tilepos: TilePos) -> Vec<TilePos> {
+        let cluster = cluster_of(&tilepos);
+        let include_diagonals = self.costs.diagonal != DiagonalMovement::Never;
+
+        Neighbors::get_square_neighboring_positions(&tilepos, &TILEMAP_SIZE, include_diagonals)
+            .iter()
+            .copied()
+            .filter(|neighbor| cluster_of(neighbor) == cluster)
+            .collect()
+    }
+
+    /// The cost of reaching each entrance of the cluster from `start` without leaving the cluster
+    fn local_costs_from(&self, start: TilePos) -> HashMap<TilePos, u32> {
+        let entrances = self.entrances(cluster_of(&start));
+        let reachable = dijkstra_all(&start, |&current| {
+            self.local_neighbors(current)
+                .into_iter()
+                .filter_map(move |neighbor| Some((neighbor, self.costs.step_cost(&self.world, current, neighbor)?)))
+        });
+
+        entrances
+            .into_iter()
+            .filter_map(|entrance| reachable.get(&entrance).map(|&(_, cost)| (entrance, cost)))
+            .collect()
+    }
+
+    /// The cost of reaching `goal` from each entrance of the cluster without leaving the cluster
+    fn local_costs_to(&self, goal: TilePos) -> HashMap<TilePos, u32> {
+        let entrances = self.entrances(cluster_of(&goal));
+        let reachable = dijkstra_all(&goal, |&current| {
+            // Searching backwards, so the step that has to be paid for is from `neighbor` onto `current`
+            self.local_neighbors(current)
+                .into_iter()
+                .filter_map(move |neighbor| Some((neighbor, self.costs.step_cost(&self.world, neighbor, current)?)))
+        });
+
+        entrances
+            .into_iter()
+            .filter_map(|entrance| reachable.get(&entrance).map(|&(_, cost)| (entrance, cost)))
+            .collect()
+    }
+
+    /// The cheapest path between two tiles of the same cluster that stays inside it
+    fn local_path(&self, start: TilePos, goal: TilePos) -> Option<Vec<TilePos>> {
+        astar(
+            &start,
+            |&current| {
+                self.local_neighbors(current)
+                    .into_iter()
+                    .filter_map(|neighbor| Some((neighbor, self.costs.step_cost(&self.world, current, neighbor)?)))
+                    .collect::<Vec<_>>()
+            },
+            |&current| self.costs.heuristic(current, goal),
+            |&current| current == goal,
+        )
+        .map(|(path, _)| path)
+    }
+}
+
+fn cluster_of(tilepos: &TilePos) -> Cluster {
+    (tilepos.x / CLUSTER_SIZE, tilepos.y / CLUSTER_SIZE)
+}
+
+fn cluster_counts() -> (u32, u32) {
+    (
+        TILEMAP_SIZE.x.div_ceil(CLUSTER_SIZE),
+        TILEMAP_SIZE.y.div_ceil(CLUSTER_SIZE),
+    )
+}
+
+/// The first and last tile of a cluster
+fn cluster_bounds(cluster: Cluster) -> (TilePos, TilePos) {
+    let min = TilePos::new(cluster.0 * CLUSTER_SIZE, cluster.1 * CLUSTER_SIZE);
+    let max = TilePos::new(
+        (min.x + CLUSTER_SIZE).min(TILEMAP_SIZE.x) - 1,
+        (min.y + CLUSTER_SIZE).min(TILEMAP_SIZE.y) - 1,
+    );
+    (min, max)
+}
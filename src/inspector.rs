@@ -1,3 +1,6 @@
+use crate::blackboard::{Blackboard, ColonyBlackboard};
+use crate::items::Inventory;
+use crate::stockpile::{LooseItem, Stockpile, StockpileCell, ZoneFilter};
 use bevy::input::common_conditions::input_toggle_active;
 use bevy::prelude::*;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
@@ -10,6 +13,13 @@ impl Plugin for InspectorPlugin {
         app.add_plugins(WorldInspectorPlugin::default().run_if(input_toggle_active(true, KeyCode::Escape)))
             .register_type::<ActionState>()
             .register_type::<Actor>()
-            .register_type::<Thinker>();
+            .register_type::<Blackboard>()
+            .register_type::<ColonyBlackboard>()
+            .register_type::<Inventory>()
+            .register_type::<LooseItem>()
+            .register_type::<Stockpile>()
+            .register_type::<StockpileCell>()
+            .register_type::<Thinker>()
+            .register_type::<ZoneFilter>();
     }
 }
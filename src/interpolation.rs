@@ -0,0 +1,72 @@
+use crate::villager::Movement;
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+
+/// Draws walking villagers in between the last two simulation steps, so that they move smoothly whatever the frame
+/// rate while the simulation itself only ever sees where the steps left them
+pub struct InterpolationPlugin;
+
+impl Plugin for InterpolationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedFirst, restore_simulated_translation_system)
+            .add_systems(FixedLast, record_simulated_translation_system)
+            .add_systems(
+                PostUpdate,
+                (start_interpolating_system, interpolate_translation_system)
+                    .chain()
+                    .before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+/// Where the simulation had an entity before and after the last step
+#[derive(Component)]
+pub struct Interpolated {
+    previous: Vec2,
+    current: Vec2,
+}
+
+impl Interpolated {
+    pub fn new(translation: Vec2) -> Self {
+        Self {
+            previous: translation,
+            current: translation,
+        }
+    }
+}
+
+fn start_interpolating_system(
+    mut commands: Commands,
+    walkers: Query<(Entity, &Transform), (With<Movement>, Without<Interpolated>)>,
+) {
+    for (entity, transform) in &walkers {
+        commands
+            .entity(entity)
+            .insert(Interpolated::new(transform.translation.xy()));
+    }
+}
+
+/// Puts entities back where the simulation left them before it takes another step
+fn restore_simulated_translation_system(mut query: Query<(&mut Transform, &mut Interpolated)>) {
+    for (mut transform, mut interpolated) in &mut query {
+        interpolated.previous = interpolated.current;
+        transform.translation.x = interpolated.current.x;
+        transform.translation.y = interpolated.current.y;
+    }
+}
+
+fn record_simulated_translation_system(mut query: Query<(&Transform, &mut Interpolated)>) {
+    for (transform, mut interpolated) in &mut query {
+        interpolated.current = transform.translation.xy();
+    }
+}
+
+fn interpolate_translation_system(fixed_time: Res<Time<Fixed>>, mut query: Query<(&mut Transform, &Interpolated)>) {
+    let alpha = fixed_time.overstep_fraction();
+
+    for (mut transform, interpolated) in &mut query {
+        let translation = interpolated.previous.lerp(interpolated.current, alpha);
+        transform.translation.x = translation.x;
+        transform.translation.y = translation.y;
+    }
+}
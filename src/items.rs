@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Everything that can be picked up and carried
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Reflect, Serialize)]
+pub enum Item {
+    Berries,
+}
+
+/// The fixed properties shared by every item of a kind
+pub struct ItemDefinition {
+    pub name: &'static str,
+    /// The most items of this kind that fit in one inventory slot
+    pub stack_size: u32,
+    /// The weight of a single item
+    pub weight: f32,
+    pub edible: bool,
+}
+
+const BERRIES: ItemDefinition = ItemDefinition {
+    name: "Berries",
+    stack_size: 20,
+    weight: 0.1,
+    edible: true,
+};
+
+impl Item {
+    pub const ALL: [Item; 1] = [Item::Berries];
+
+    pub fn definition(self) -> &'static ItemDefinition {
+        match self {
+            Item::Berries => &BERRIES,
+        }
+    }
+}
+
+/// A number of items of one kind occupying a single inventory slot
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Reflect, Serialize)]
+pub struct ItemStack {
+    pub item: Item,
+    pub count: u32,
+}
+
+/// The items an entity is carrying, limited both by the number of stacks and by their total weight.
+///
+/// # Examples
+///
+/// ```
+/// use bevy_game::items::{Inventory, Item};
+///
+/// let mut inventory = Inventory::new(2, 3.0);
+///
+/// // Two stacks of 20 berries fit, but they would weigh 4.0 so only 30 are taken
+/// assert_eq!(inventory.add(Item::Berries, 50), 20);
+/// assert_eq!(inventory.count(Item::Berries), 30);
+/// assert_eq!(inventory.stacks().len(), 2);
+///
+/// assert_eq!(inventory.remove(Item::Berries, 25), 25);
+/// assert_eq!(inventory.count(Item::Berries), 5);
+/// assert_eq!(inventory.stacks().len(), 1);
+/// ```
+#[derive(Clone, Component, Debug, Deserialize, Reflect, Serialize)]
+#[reflect(Component)]
+pub struct Inventory {
+    stacks: Vec<ItemStack>,
+    /// How many stacks can be carried
+    pub slots: usize,
+    /// The heaviest load that can be carried
+    pub max_weight: f32,
+}
+
+impl Inventory {
+    pub fn new(slots: usize, max_weight: f32) -> Self {
+        Self {
+            stacks: vec![],
+            slots,
+            max_weight,
+        }
+    }
+
+    pub fn stacks(&self) -> &[ItemStack] {
+        &self.stacks
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stacks.is_empty()
+    }
+
+    /// Returns how many items of a kind are carried across all stacks.
+    pub fn count(&self, item: Item) -> u32 {
+        self.stacks
+            .iter()
+            .filter(|stack| stack.item == item)
+            .map(|stack| stack.count)
+            .sum()
+    }
+
+    /// Returns the total weight of everything carried.
+    pub fn weight(&self) -> f32 {
+        self.stacks
+            .iter()
+            .map(|stack| stack.item.definition().weight * stack.count as f32)
+            .sum()
+    }
+
+    /// Returns how many more items of a kind would fit, by both slots and weight.
+    pub fn space_for(&self, item: Item) -> u32 {
+        let definition = item.definition();
+
+        let in_open_stacks: u32 = self
+            .stacks
+            .iter()
+            .filter(|stack| stack.item == item)
+            .map(|stack| definition.stack_size - stack.count)
+            .sum();
+        let in_free_slots = self.slots.saturating_sub(self.stacks.len()) as u32 * definition.stack_size;
+
+        let by_weight = if definition.weight > 0.0 {
+            // Rounding keeps a load that is exactly at the limit from being refused
+            ((self.max_weight - self.weight()) / definition.weight + 1e-3).max(0.0) as u32
+        } else {
+            u32::MAX
+        };
+
+        (in_open_stacks + in_free_slots).min(by_weight)
+    }
+
+    /// Adds as many of the items as fit, topping up existing stacks first, and returns how many were left over.
+    pub fn add(&mut self, item: Item, count: u32) -> u32 {
+        let stack_size = item.definition().stack_size;
+        let mut remaining = count.min(self.space_for(item));
+        let left_over = count - remaining;
+
+        for stack in self.stacks.iter_mut().filter(|stack| stack.item == item) {
+            let added = remaining.min(stack_size - stack.count);
+            stack.count += added;
+            remaining -= added;
+        }
+
+        while remaining > 0 {
+            let added = remaining.min(stack_size);
+            self.stacks.push(ItemStack { item, count: added });
+            remaining -= added;
+        }
+
+        left_over
+    }
+
+    /// Removes up to `count` items of a kind, emptying the smallest stacks first, and returns how many were removed.
+    pub fn remove(&mut self, item: Item, count: u32) -> u32 {
+        let mut remaining = count;
+        self.stacks.sort_by_key(|stack| (stack.item != item, stack.count));
+
+        for stack in self.stacks.iter_mut().filter(|stack| stack.item == item) {
+            let removed = remaining.min(stack.count);
+            stack.count -= removed;
+            remaining -= removed;
+        }
+
+        self.stacks.retain(|stack| stack.count > 0);
+        count - remaining
+    }
+
+    /// Returns the first edible item carried, if any.
+    pub fn edible(&self) -> Option<Item> {
+        self.stacks
+            .iter()
+            .map(|stack| stack.item)
+            .find(|item| item.definition().edible)
+    }
+}
@@ -0,0 +1,319 @@
+use crate::agent::{Bush, Rock, Tree};
+use crate::designation::Designated;
+use crate::reservations::{Reservable, Reserved};
+use crate::states::States::Play;
+use crate::SimulationSet;
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::TilePos;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The most urgent priority a job or a kind of work can have
+pub const HIGHEST_PRIORITY: u8 = 1;
+
+/// The least urgent priority a job or a kind of work can have
+pub const LOWEST_PRIORITY: u8 = 4;
+
+/// The priority new jobs are posted with and villagers start out doing every kind of work at
+pub const DEFAULT_PRIORITY: u8 = 3;
+
+/// How much a work score is raised, relative to `DEFAULT_PRIORITY`, by each step up in priority
+const PRIORITY_STEP: f32 = 0.2;
+
+pub struct JobsPlugin;
+
+impl Plugin for JobsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<JobBoard>().add_systems(
+            FixedUpdate,
+            (post_jobs_system, prune_jobs_system)
+                .chain()
+                .in_set(SimulationSet::Prepare)
+                .run_if(in_state(Play)),
+        );
+    }
+}
+
+/// The kinds of work a villager can be given priorities for, all but hauling are posted as jobs by designations
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum JobKind {
+    Gather,
+    Chop,
+    Mine,
+    Haul,
+}
+
+impl JobKind {
+    pub const ALL: [JobKind; 4] = [JobKind::Gather, JobKind::Chop, JobKind::Mine, JobKind::Haul];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            JobKind::Gather => "Gather",
+            JobKind::Chop => "Chop",
+            JobKind::Mine => "Mine",
+            JobKind::Haul => "Haul",
+        }
+    }
+
+    /// The skill that makes a villager better at this kind of work, if any
+    pub fn skill(self) -> Option<Skill> {
+        match self {
+            JobKind::Gather => Some(Skill::Plants),
+            JobKind::Chop => Some(Skill::Woodcutting),
+            JobKind::Mine => Some(Skill::Mining),
+            JobKind::Haul => None,
+        }
+    }
+
+    /// The lowest level of its skill a villager needs to be given a job of this kind, so that nobody fells trees or
+    /// breaks rocks without having learned how
+    pub fn required_skill(self) -> u32 {
+        match self {
+            JobKind::Gather | JobKind::Haul => 0,
+            JobKind::Chop | JobKind::Mine => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Skill {
+    Plants,
+    Woodcutting,
+    Mining,
+}
+
+/// How good a villager is at each skill, skills that were never trained are at level 0
+#[derive(Clone, Component, Debug, Default, Deserialize, Serialize)]
+pub struct Skills(HashMap<Skill, u32>);
+
+impl Skills {
+    pub fn level(&self, skill: Skill) -> u32 {
+        self.0.get(&skill).copied().unwrap_or_default()
+    }
+
+    /// Returns the level of the skill a kind of work needs, work that needs no skill is at level 0.
+    pub fn level_for(&self, kind: JobKind) -> u32 {
+        kind.skill().map_or(0, |skill| self.level(skill))
+    }
+
+    pub fn set_level(&mut self, skill: Skill, level: u32) {
+        self.0.insert(skill, level);
+    }
+}
+
+/// Which kinds of work a villager takes on and in what order, from `HIGHEST_PRIORITY` to `LOWEST_PRIORITY`.
+///
+/// Kinds of work without a priority are never taken on.
+#[derive(Clone, Component, Debug, Deserialize, Serialize)]
+pub struct WorkPriorities(HashMap<JobKind, u8>);
+
+impl WorkPriorities {
+    /// Returns the priority of a kind of work, or `None` if it is switched off.
+    pub fn get(&self, kind: JobKind) -> Option<u8> {
+        self.0.get(&kind).copied()
+    }
+
+    /// Sets the priority of a kind of work, clamped to `HIGHEST_PRIORITY..=LOWEST_PRIORITY`, or switches it off.
+    pub fn set(&mut self, kind: JobKind, priority: Option<u8>) {
+        match priority {
+            Some(priority) => self.0.insert(kind, priority.clamp(HIGHEST_PRIORITY, LOWEST_PRIORITY)),
+            None => self.0.remove(&kind),
+        };
+    }
+
+    /// Scales the score of a kind of work by its priority, leaving it as is at `DEFAULT_PRIORITY`.
+    ///
+    /// Work that is switched off scores 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bevy_game::jobs::{JobKind, WorkPriorities, DEFAULT_PRIORITY};
+    ///
+    /// let mut priorities = WorkPriorities::default();
+    /// assert_eq!(priorities.weigh(JobKind::Haul, 0.5), 0.5);
+    ///
+    /// priorities.set(JobKind::Haul, Some(DEFAULT_PRIORITY - 1));
+    /// assert!(priorities.weigh(JobKind::Haul, 0.5) > 0.5);
+    ///
+    /// priorities.set(JobKind::Haul, None);
+    /// assert_eq!(priorities.weigh(JobKind::Haul, 0.5), 0.0);
+    /// ```
+    pub fn weigh(&self, kind: JobKind, score: f32) -> f32 {
+        self.get(kind).map_or(0.0, |priority| weigh_priority(priority, score))
+    }
+}
+
+/// Scales a score by a priority, leaving it as is at `DEFAULT_PRIORITY` and keeping it within 0.0..=1.0
+pub fn weigh_priority(priority: u8, score: f32) -> f32 {
+    let steps = DEFAULT_PRIORITY as f32 - priority as f32;
+    (score * (1.0 + steps * PRIORITY_STEP)).clamp(0.0, 1.0)
+}
+
+impl Default for WorkPriorities {
+    fn default() -> Self {
+        Self(JobKind::ALL.into_iter().map(|kind| (kind, DEFAULT_PRIORITY)).collect())
+    }
+}
+
+/// Something that needs doing somewhere, posted when its target is designated and taken down once it is done or
+/// the designation is cancelled
+#[derive(Clone, Component, Debug)]
+pub struct Job {
+    pub kind: JobKind,
+    /// From `HIGHEST_PRIORITY` to `LOWEST_PRIORITY`
+    pub priority: u8,
+    /// The lowest level of the kind's skill a villager needs to take the job
+    pub required_skill: u32,
+    pub location: TilePos,
+    /// The entity being worked on, which is reserved while the job is taken
+    pub target: Entity,
+}
+
+impl Job {
+    /// Returns true if a villager with these priorities and skills would take the job.
+    pub fn suits(&self, priorities: &WorkPriorities, skills: &Skills) -> bool {
+        priorities.get(self.kind).is_some() && skills.level_for(self.kind) >= self.required_skill
+    }
+}
+
+/// Every posted job, by the entity it targets
+#[derive(Default, Resource)]
+pub struct JobBoard {
+    jobs: HashMap<Entity, Entity>,
+}
+
+impl JobBoard {
+    /// Returns the job entity posted for a target, if there is one.
+    pub fn job_for(&self, target: Entity) -> Option<Entity> {
+        self.jobs.get(&target).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Returns the entities of every posted job.
+    pub fn jobs(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.jobs.values().copied()
+    }
+
+    /// Spawns a job and posts it for its target, returning the job entity.
+    pub fn post(&mut self, commands: &mut Commands, job: Job) -> Entity {
+        let target = job.target;
+        let job = commands.spawn((Name::new("Job"), job)).id();
+        self.jobs.insert(target, job);
+        job
+    }
+}
+
+/// Returns the jobs that suit a worker, best first.
+///
+/// Jobs are ranked by how highly the worker prioritizes their kind, then by their own priority, then by how skilled
+/// the worker is at them and finally by how close they are to `position`.
+///
+/// # Examples
+///
+/// ```
+/// use bevy::prelude::Entity;
+/// use bevy_ecs_tilemap::prelude::TilePos;
+/// use bevy_game::jobs::{rank_jobs, Job, JobKind, Skills, WorkPriorities, DEFAULT_PRIORITY};
+///
+/// let job = |kind, priority, x| Job {
+///     kind,
+///     priority,
+///     required_skill: 0,
+///     location: TilePos { x, y: 0 },
+///     target: Entity::from_raw(x),
+/// };
+/// let jobs = [
+///     job(JobKind::Gather, DEFAULT_PRIORITY, 1),
+///     job(JobKind::Gather, DEFAULT_PRIORITY, 8),
+///     job(JobKind::Gather, 1, 9),
+///     job(JobKind::Chop, DEFAULT_PRIORITY, 2),
+/// ];
+///
+/// let mut priorities = WorkPriorities::default();
+/// priorities.set(JobKind::Chop, None);
+///
+/// let ranked: Vec<u32> = rank_jobs(&jobs, &priorities, &Skills::default(), TilePos { x: 0, y: 0 })
+///     .iter()
+///     .map(|job| job.location.x)
+///     .collect();
+/// assert_eq!(ranked, [9, 1, 8]);
+/// ```
+pub fn rank_jobs<'a>(
+    jobs: impl IntoIterator<Item = &'a Job>,
+    priorities: &WorkPriorities,
+    skills: &Skills,
+    position: TilePos,
+) -> Vec<&'a Job> {
+    let mut suitable: Vec<_> = jobs
+        .into_iter()
+        .filter(|job| job.suits(priorities, skills))
+        .map(|job| {
+            let distance = position.x.abs_diff(job.location.x) + position.y.abs_diff(job.location.y);
+            let key = (
+                priorities.get(job.kind),
+                job.priority,
+                std::cmp::Reverse(skills.level_for(job.kind)),
+                distance,
+            );
+            (key, job)
+        })
+        .collect();
+
+    suitable.sort_by_key(|(key, _)| *key);
+    suitable.into_iter().map(|(_, job)| job).collect()
+}
+
+/// Posts a job for every newly designated bush, tree and rock, at the priority it was designated at
+fn post_jobs_system(
+    mut commands: Commands,
+    mut board: ResMut<JobBoard>,
+    designated: Query<
+        (Entity, &TilePos, Option<&Designated>, Has<Bush>, Has<Tree>, Has<Rock>),
+        Or<(Added<Reservable>, Added<Reserved>)>,
+    >,
+) {
+    for (target, &location, designation, bush, tree, rock) in designated.iter() {
+        if board.jobs.contains_key(&target) {
+            continue;
+        }
+
+        let kind = match (bush, tree, rock) {
+            (true, _, _) => JobKind::Gather,
+            (_, true, _) => JobKind::Chop,
+            (_, _, true) => JobKind::Mine,
+            _ => continue,
+        };
+
+        let job = Job {
+            kind,
+            priority: designation.map_or(DEFAULT_PRIORITY, |designation| designation.priority),
+            required_skill: kind.required_skill(),
+            location,
+            target,
+        };
+        board.post(&mut commands, job);
+    }
+}
+
+/// Takes down jobs whose target is gone or no longer designated
+fn prune_jobs_system(
+    mut commands: Commands,
+    mut board: ResMut<JobBoard>,
+    designated: Query<(), Or<(With<Reservable>, With<Reserved>)>>,
+) {
+    board.jobs.retain(|&target, &mut job| {
+        let open = designated.contains(target);
+        if !open {
+            commands.entity(job).despawn();
+        }
+        open
+    });
+}
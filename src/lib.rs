@@ -1,18 +1,32 @@
-#![allow(clippy::type_complexity)]
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
 
 pub mod agent;
 pub mod animation;
 mod assets;
 pub mod audio;
+pub mod behavior;
 pub mod blackboard;
+pub mod designation;
 pub mod ext;
+pub mod hierarchy;
 mod inspector;
+pub mod interpolation;
+pub mod items;
+pub mod jobs;
 pub mod loading;
 mod marquee;
 pub mod menu;
+pub mod navigation;
+pub mod needs;
+pub mod orders;
+pub mod planner;
 pub mod reservations;
-mod states;
+pub mod save;
+pub mod selection;
+pub mod states;
+pub mod stockpile;
 pub mod villager;
+pub mod work_panel;
 pub mod worldgen;
 
 use crate::animation::AnimationPlugin;
@@ -20,40 +34,67 @@ use crate::audio::InternalAudioPlugin;
 use crate::loading::LoadingPlugin;
 use crate::menu::MenuPlugin;
 use crate::villager::VillagerPlugin;
-use crate::worldgen::WorldgenPlugin;
+use crate::work_panel::WorkPanelPlugin;
+use crate::worldgen::{WorldgenPlugin, WorldgenRenderPlugin};
 
 use crate::agent::AgentPlugin;
+use crate::behavior::BehaviorPlugin;
+use crate::blackboard::BlackboardPlugin;
+use crate::designation::{DesignationPlugin, DesignationToolbarPlugin};
+use crate::interpolation::InterpolationPlugin;
+use crate::jobs::JobsPlugin;
 use crate::marquee::InputPlugin;
-use crate::reservations::ReservationsPlugin;
+use crate::navigation::{NavigationPlugin, Pathfinder};
+use crate::needs::{NeedsPlugin, NeedsRenderPlugin};
+use crate::orders::OrdersPlugin;
+use crate::planner::PlannerPlugin;
+use crate::reservations::{ReservationsPlugin, ReservationsRenderPlugin};
+use crate::save::SavePlugin;
+use crate::selection::SelectionPlugin;
+use crate::stockpile::{StockpilePlugin, StockpileRenderPlugin, ZoneFilterToolbarPlugin};
 use bevy::app::App;
+use bevy::ecs::schedule::ExecutorKind;
 use bevy::prelude::*;
 use bevy_pancam::PanCamPlugin;
 use bevy_rapier2d::prelude::*;
+use big_brain::actions::{concurrent_system, steps_system};
+use big_brain::prelude::BigBrainSet;
 use big_brain::BigBrainPlugin;
 use seldom_state::StateMachinePlugin;
 
 pub const ENTITY_SIZE_IN_PIXELS: f32 = 16.0;
 pub const ENTITY_SIZE_IN_METERS: f32 = 1.0;
 
+/// How many steps the simulation takes every second, whatever the frame rate
+pub const SIMULATION_STEPS_PER_SECOND: f64 = 60.0;
+
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
+        app.add_plugins(SimulationPlugin);
+
+        // Presentation Plugins
         app.add_plugins((
-            AgentPlugin,
             AnimationPlugin,
+            DesignationToolbarPlugin,
             LoadingPlugin,
             assets::AssetsPlugin,
-            BigBrainPlugin::new(PreUpdate),
             inspector::InspectorPlugin,
             InternalAudioPlugin,
+            InterpolationPlugin,
             MenuPlugin,
             PanCamPlugin,
-            ReservationsPlugin,
-            states::StatesPlugin,
+            SavePlugin,
             StateMachinePlugin,
-            VillagerPlugin,
-            WorldgenPlugin,
+            WorkPanelPlugin,
+            (
+                NeedsRenderPlugin,
+                ReservationsRenderPlugin,
+                StockpileRenderPlugin,
+                WorldgenRenderPlugin,
+                ZoneFilterToolbarPlugin,
+            ),
         ));
 
         // Physics Plugins
@@ -61,6 +102,101 @@ impl Plugin for GamePlugin {
         app.add_plugins(RapierDebugRenderPlugin::default());
 
         // Player Input Plugins
-        app.add_plugins(InputPlugin);
+        app.add_plugins((InputPlugin, SelectionPlugin));
+    }
+}
+
+/// Everything needed to generate and simulate a colony, without windowing, rendering, audio or player input.
+///
+/// The colony is simulated in `FixedUpdate`, `SIMULATION_STEPS_PER_SECOND` times a second, so that it plays out the
+/// same at any frame rate. `GamePlugin` builds on top of this, and the headless binary runs it on its own.
+pub struct SimulationPlugin;
+
+/// The phases of a simulation step in `FixedUpdate`, in the order they run.
+///
+/// Systems in different phases never race each other for the same data, so a step always plays out the same way.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, SystemSet)]
+pub enum SimulationSet {
+    /// Catches up on paths, jobs and blackboards before anyone thinks
+    Prepare,
+    /// Writes what agents notice onto their blackboards
+    Sense,
+    /// Plans goals, after big-brain ran the steps and concurrent actions they are nested in
+    Plan,
+    Orders,
+    Needs,
+    Work,
+    Haul,
+    /// Applies what the actions asked for: designations, behaviors, needs decay and new path searches
+    Settle,
+    /// Grants and releases the reservations requested during the step
+    Reserve,
+    /// Walks villagers along their paths
+    Move,
+}
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_hz(SIMULATION_STEPS_PER_SECOND))
+            .configure_sets(
+                FixedUpdate,
+                (
+                    (SimulationSet::Prepare, SimulationSet::Sense)
+                        .chain()
+                        .before(BigBrainSet::Scorers),
+                    (
+                        SimulationSet::Plan,
+                        SimulationSet::Orders,
+                        SimulationSet::Needs,
+                        SimulationSet::Work,
+                        SimulationSet::Haul,
+                    )
+                        .chain()
+                        .in_set(BigBrainSet::Actions)
+                        .after(steps_system)
+                        .after(concurrent_system),
+                    (SimulationSet::Settle, SimulationSet::Reserve, SimulationSet::Move)
+                        .chain()
+                        .after(BigBrainSet::Actions),
+                ),
+            );
+        app.add_plugins((
+            AgentPlugin,
+            BehaviorPlugin,
+            BigBrainPlugin::new(FixedUpdate).set_cleanup_schedule(FixedLast),
+            BlackboardPlugin,
+            DesignationPlugin,
+            JobsPlugin,
+            NavigationPlugin,
+            NeedsPlugin,
+            OrdersPlugin,
+            PlannerPlugin,
+            ReservationsPlugin,
+            states::StatesPlugin,
+            StockpilePlugin,
+            VillagerPlugin,
+            WorldgenPlugin,
+        ));
+    }
+}
+
+/// Makes runs with the same seed play out exactly the same, at the cost of running the simulation in `FixedUpdate` on a
+/// single thread and searching for paths on it too.
+///
+/// Add it after `SimulationPlugin`.
+pub struct DeterministicPlugin;
+
+impl Plugin for DeterministicPlugin {
+    fn build(&self, app: &mut App) {
+        app.world_mut().resource_mut::<Pathfinder>().blocking = true;
+    }
+
+    fn finish(&self, app: &mut App) {
+        // `SimulationSet` orders the systems that touch the same data, but the ones in between still spawn entities in
+        // whichever order the threads get to them. Drawing and input do not feed back into the simulation, so the other
+        // schedules keep their threads.
+        app.edit_schedule(FixedUpdate, |schedule| {
+            schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+        });
     }
 }
@@ -3,16 +3,15 @@ use bevy::prelude::*;
 use bevy_asset_loader::prelude::*;
 use iyes_progress::{ProgressCounter, ProgressPlugin};
 
-use crate::states::States::{LoadMenu, LoadPlay, Menu, Play, Worldgen};
+use crate::states::States::{LoadMenu, LoadPlay, Menu, Worldgen};
 
 pub struct LoadingPlugin;
 
 impl Plugin for LoadingPlugin {
     fn build(&self, app: &mut App) {
+        // The worldgen progress plugins are part of `WorldgenPlugin` so that they also run headless
         app.add_plugins((
             ProgressPlugin::new(LoadMenu).continue_to(Menu),
-            ProgressPlugin::new(LoadPlay).continue_to(Play),
-            ProgressPlugin::new(Worldgen).continue_to(LoadPlay),
             FrameTimeDiagnosticsPlugin,
         ))
         .add_systems(
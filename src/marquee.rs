@@ -1,4 +1,4 @@
-use crate::reservations::Reservable;
+use crate::designation::{DesignationEvent, DesignationMode, DesignationPriority};
 use crate::states::States::Play;
 use crate::ENTITY_SIZE_IN_PIXELS;
 use bevy::input::mouse::MouseButtonInput;
@@ -41,9 +41,23 @@ fn mouse_input_handler(
     mut events: EventReader<MouseButtonInput>,
     q_window: Query<&Window>,
     q_camera: Query<(&Camera, &GlobalTransform)>,
-    q_marquee: Query<Entity, With<MarqueeSelection>>,
+    mode: Res<DesignationMode>,
+    priority: Res<DesignationPriority>,
+    q_buttons: Query<&Interaction, With<Button>>,
+    q_marquee: Query<(Entity, &MarqueeSelection)>,
+    mut designation_writer: EventWriter<DesignationEvent>,
 ) {
     for event in events.read() {
+        if event.button != MouseButton::Left {
+            continue;
+        }
+
+        // Clicks on the toolbar are not meant for the map
+        if event.state == ButtonState::Pressed && q_buttons.iter().any(|interaction| *interaction != Interaction::None)
+        {
+            continue;
+        }
+
         let (camera, camera_transform) = q_camera.single();
         let window = q_window.single();
         if let Some(cursor_position) = window
@@ -63,7 +77,14 @@ fn mouse_input_handler(
                         .insert(CollisionGroups::new(SELECTION_GROUP, SELECTABLE_GROUP));
                 }
                 ButtonState::Released => {
-                    if let Ok(marquee_entity) = q_marquee.get_single() {
+                    if let Ok((marquee_entity, marquee)) = q_marquee.get_single() {
+                        designation_writer.send(DesignationEvent {
+                            kind: mode.0,
+                            priority: priority.0,
+                            entities: marquee.selected.iter().copied().collect(),
+                            rect: Rect::from_corners(marquee.start, marquee.end),
+                        });
+
                         commands.entity(marquee_entity).despawn();
                     }
                 }
@@ -122,7 +143,6 @@ fn mouse_motion_handler(
 }
 
 fn handle_collision_events(
-    mut commands: Commands,
     mut events: EventReader<CollisionEvent>,
     mut q_marquee: Query<(Entity, &mut MarqueeSelection)>,
 ) {
@@ -132,12 +152,8 @@ fn handle_collision_events(
                 CollisionEvent::Started(e1, e2, _flags) => {
                     if *e1 == marquee_entity {
                         marquee.selected.insert(*e2);
-                        // TODO(jesse): Refactor this to make this event driven with selectable events
-                        commands.entity(*e2).insert(Reservable);
                     } else if *e2 == marquee_entity {
                         marquee.selected.insert(*e1);
-                        // TODO(jesse): Refactor this to make this event driven with selectable events
-                        commands.entity(*e1).insert(Reservable);
                     }
                 }
                 CollisionEvent::Stopped(e1, e2, _flags) => {
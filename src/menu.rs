@@ -3,6 +3,8 @@ use bevy_nine_slice_ui::{NineSliceUiMaterialBundle, NineSliceUiPlugin, NineSlice
 use bevy_pancam::{DirectionKeys, PanCam};
 
 use crate::assets::UiAssets;
+use crate::save::LoadColony;
+use crate::worldgen::WorldSeed;
 
 pub struct MenuPlugin;
 
@@ -12,7 +14,12 @@ impl Plugin for MenuPlugin {
             .add_systems(OnEnter(crate::states::States::Menu), setup_menu)
             .add_systems(
                 Update,
-                (button_style_system, play_button_clicked_system).run_if(in_state(crate::states::States::Menu)),
+                (
+                    button_style_system,
+                    play_button_clicked_system,
+                    continue_button_clicked_system,
+                )
+                    .run_if(in_state(crate::states::States::Menu)),
             )
             .add_systems(OnExit(crate::states::States::Menu), cleanup_menu)
             .add_systems(OnEnter(crate::states::States::Worldgen), setup_load_play_ui)
@@ -94,51 +101,35 @@ fn setup_menu(mut commands: Commands, ui_assets: Res<UiAssets>) {
 
     let middle_id = commands.spawn(middle).id();
 
-    let play_button_id = commands
-        .spawn(ButtonBundle {
-            background_color: BackgroundColor(Color::NONE),
-            style: Style {
-                width: Val::Px(400.0),
-                height: Val::Px(120.0),
-                justify_content: JustifyContent::Center,
-                align_items: AlignItems::Center,
-                ..default()
-            },
-            ..default()
-        })
-        .with_children(|parent| {
-            parent
-                .spawn(NineSliceUiMaterialBundle {
-                    style: Style {
-                        width: Val::Percent(100.),
-                        height: Val::Percent(100.),
-                        align_items: AlignItems::Center,
-                        justify_content: JustifyContent::Center,
-                        display: Display::Flex,
-                        ..default()
-                    },
-                    nine_slice_texture: NineSliceUiTexture::from_slice(
-                        ui_assets.buttons_image.clone(),
-                        Rect::new(0., 0., 48., 48.),
-                    ),
-                    ..default()
-                })
-                .with_children(|children| {
-                    children.spawn(TextBundle::from_section(
-                        "Play",
-                        TextStyle {
-                            font_size: 48.0,
-                            color: Color::WHITE,
-                            ..default()
-                        },
-                    ));
-                });
-        })
+    let play_button_id = spawn_button(&mut commands, &ui_assets, "Play");
+    commands
+        .entity(play_button_id)
         .insert(bevy::prelude::Name::new("Play Button"))
-        .insert(PlayButton)
-        .id();
+        .insert(PlayButton);
+
+    let continue_button_id = spawn_button(&mut commands, &ui_assets, "Continue");
+    commands
+        .entity(continue_button_id)
+        .insert(bevy::prelude::Name::new("Continue Button"))
+        .insert(ContinueButton);
 
-    let exit_button_id = commands
+    let exit_button_id = spawn_button(&mut commands, &ui_assets, "Exit");
+    commands
+        .entity(exit_button_id)
+        .insert(bevy::prelude::Name::new("Exit Button"));
+
+    commands
+        .entity(middle_id)
+        .push_children(&[play_button_id, continue_button_id, exit_button_id]);
+
+    let right_id = commands.spawn(right).id();
+    let _root_id = commands
+        .spawn((Menu, bevy::prelude::Name::new("Menu"), root))
+        .push_children(&[left_id, middle_id, right_id]);
+}
+
+fn spawn_button(commands: &mut Commands, ui_assets: &UiAssets, label: &str) -> Entity {
+    commands
         .spawn(ButtonBundle {
             background_color: BackgroundColor(Color::NONE),
             style: Style {
@@ -169,7 +160,7 @@ fn setup_menu(mut commands: Commands, ui_assets: Res<UiAssets>) {
                 })
                 .with_children(|children| {
                     children.spawn(TextBundle::from_section(
-                        "Exit",
+                        label,
                         TextStyle {
                             font_size: 48.0,
                             color: Color::WHITE,
@@ -178,17 +169,7 @@ fn setup_menu(mut commands: Commands, ui_assets: Res<UiAssets>) {
                     ));
                 });
         })
-        .insert(bevy::prelude::Name::new("Exit Button"))
-        .id();
-
-    commands
-        .entity(middle_id)
-        .push_children(&[play_button_id, exit_button_id]);
-
-    let right_id = commands.spawn(right).id();
-    let _root_id = commands
-        .spawn((Menu, bevy::prelude::Name::new("Menu"), root))
-        .push_children(&[left_id, middle_id, right_id]);
+        .id()
 }
 
 fn cleanup_menu(mut commands: Commands, menu: Query<Entity, With<Menu>>) {
@@ -200,21 +181,38 @@ fn cleanup_menu(mut commands: Commands, menu: Query<Entity, With<Menu>>) {
 #[derive(Component)]
 struct LoadMenu;
 
-fn setup_load_play_ui(mut commands: Commands) {
+fn setup_load_play_ui(mut commands: Commands, seed: Res<WorldSeed>) {
     let start = std::time::Instant::now();
 
-    commands.spawn((
-        NodeBundle {
-            style: Style {
-                height: Val::Percent(100.0),
-                width: Val::Percent(100.0),
+    info!("Generating world with seed {}", seed.0);
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    height: Val::Percent(100.0),
+                    width: Val::Percent(100.0),
+                    align_items: AlignItems::End,
+                    justify_content: JustifyContent::End,
+                    padding: UiRect::all(Val::Percent(2.0)),
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::rgb_u8(238, 232, 213)), // Solarized Base2
                 ..default()
             },
-            background_color: BackgroundColor(Color::rgb_u8(238, 232, 213)), // Solarized Base2
-            ..default()
-        },
-        LoadMenu,
-    ));
+            LoadMenu,
+        ))
+        .with_children(|children| {
+            // Show the seed so that reported worlds can be regenerated exactly
+            children.spawn(TextBundle::from_section(
+                format!("Seed {}", seed.0),
+                TextStyle {
+                    font_size: 24.0,
+                    color: Color::srgb_u8(88, 110, 117), // Solarized Base01
+                    ..default()
+                },
+            ));
+        });
 
     info!("returned in {}ms", start.elapsed().as_millis());
 }
@@ -269,3 +267,21 @@ fn play_button_clicked_system(
         }
     }
 }
+
+#[derive(Component)]
+struct ContinueButton;
+
+fn continue_button_clicked_system(
+    interactions: Query<&Interaction, (Changed<Interaction>, With<ContinueButton>)>,
+    mut load_writer: EventWriter<LoadColony>,
+) {
+    for interaction in interactions.iter() {
+        match interaction {
+            Interaction::Pressed => {
+                load_writer.send(LoadColony);
+            }
+            Interaction::Hovered => {}
+            Interaction::None => {}
+        }
+    }
+}
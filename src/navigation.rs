@@ -0,0 +1,537 @@
+use crate::ext::TilePosExt;
+use crate::hierarchy::{HierarchicalGraph, CLUSTER_SIZE};
+use crate::villager::{find_path, DiagonalMovement, MovementCosts};
+use crate::worldgen::{World, TILEMAP_SIZE};
+use crate::SimulationSet;
+use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy_ecs_tilemap::helpers::square_grid::neighbors::Neighbors;
+use bevy_ecs_tilemap::prelude::TilePos;
+use grid_2d::{Grid, Size};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Once this many paths are cached the cache is cleared rather than growing without bound
+const MAX_CACHED_PATHS: usize = 4096;
+
+/// Flow fields are much larger than paths, so only a handful of destinations are kept at a time
+const MAX_CACHED_FLOW_FIELDS: usize = 16;
+
+/// Searches between tiles further apart than this, in tiles along either axis, plan on the `HierarchicalGraph`
+const HIERARCHICAL_SEARCH_DISTANCE: u32 = 2 * CLUSTER_SIZE;
+
+pub struct NavigationPlugin;
+
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Pathfinder>()
+            .init_resource::<Regions>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    invalidate_pathfinder_system,
+                    receive_paths_system,
+                    update_hierarchy_system,
+                    update_regions_system,
+                )
+                    .chain()
+                    .in_set(SimulationSet::Prepare)
+                    .run_if(resource_exists::<World>),
+            )
+            .add_systems(
+                FixedUpdate,
+                spawn_path_searches_system
+                    .in_set(SimulationSet::Settle)
+                    .run_if(resource_exists::<World>),
+            );
+    }
+}
+
+/// The answer to a path request made through `Pathfinder::query`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PathQuery {
+    Found(Vec<TilePos>),
+    NotFound,
+    /// The search is queued or running on the task pool, ask again on a later frame
+    Pending,
+}
+
+struct PathResult {
+    generation: u32,
+    start: TilePos,
+    goal: TilePos,
+    path: Option<Vec<TilePos>>,
+}
+
+/// Shared pathfinding service which remembers the results of previous searches.
+///
+/// Searches requested through `query` run on the `AsyncComputeTaskPool`, at most `searches_per_frame` of them
+/// starting each step, so that long or failing searches never stall a frame. Their results land in the cache. When
+/// `blocking` is set they run right away on the main thread instead, so that they always finish on the same step.
+/// Searches spanning more than a couple of clusters plan on the `HierarchicalGraph`, shorter ones use flat A*.
+///
+/// Both found and missing paths are cached, since a failed search has to explore every reachable tile. Everything
+/// is forgotten whenever the `World` or `MovementCosts` change, as any cached route might have become invalid.
+#[derive(Resource)]
+pub struct Pathfinder {
+    /// How many queued searches may be started on the task pool each step
+    pub searches_per_frame: usize,
+    /// Whether to search on the main thread rather than on the task pool
+    pub blocking: bool,
+    paths: HashMap<(TilePos, TilePos), Option<Vec<TilePos>>>,
+    flow_fields: HashMap<Vec<TilePos>, FlowField>,
+    queue: VecDeque<(TilePos, TilePos)>,
+    pending: HashSet<(TilePos, TilePos)>,
+    /// The graph, and the world and costs within it, that searches run against, shared with the tasks
+    snapshot: Option<Arc<HierarchicalGraph>>,
+    /// Bumped on every invalidation so that results of searches against an old snapshot are ignored
+    generation: u32,
+    sender: Sender<PathResult>,
+    receiver: Mutex<Receiver<PathResult>>,
+}
+
+impl Default for Pathfinder {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+
+        Self {
+            searches_per_frame: 4,
+            blocking: false,
+            paths: HashMap::default(),
+            flow_fields: HashMap::default(),
+            queue: VecDeque::default(),
+            pending: HashSet::default(),
+            snapshot: None,
+            generation: 0,
+            sender,
+            receiver: Mutex::new(receiver),
+        }
+    }
+}
+
+impl Pathfinder {
+    /// Returns the cached path from `start` to `goal`, queueing a search if it has not been requested yet.
+    pub fn query(&mut self, start: TilePos, goal: TilePos) -> PathQuery {
+        match self.paths.get(&(start, goal)) {
+            Some(Some(path)) => PathQuery::Found(path.clone()),
+            Some(None) => PathQuery::NotFound,
+            None => {
+                if self.pending.insert((start, goal)) {
+                    self.queue.push_back((start, goal));
+                }
+                PathQuery::Pending
+            }
+        }
+    }
+
+    /// Like `find_path`, but answers repeated requests from the cache. This blocks until the search is done.
+    pub fn find_path(
+        &mut self,
+        world: &World,
+        costs: &MovementCosts,
+        start: TilePos,
+        goal: TilePos,
+    ) -> Option<Vec<TilePos>> {
+        if let Some(path) = self.paths.get(&(start, goal)) {
+            return path.clone();
+        }
+
+        let path = find_path(world, costs, start, goal);
+        self.cache_path(start, goal, path.clone());
+        path
+    }
+
+    /// Returns a flow field leading to the nearest of `goals`, building it if it is not cached.
+    ///
+    /// A flow field costs about as much as a single failed path search, but afterwards any number of agents can
+    /// route towards the goals without searching, which makes it the better choice for popular destinations.
+    pub fn flow_field(&mut self, world: &World, costs: &MovementCosts, goals: &[TilePos]) -> &FlowField {
+        let mut key = goals.to_vec();
+        key.sort_by_key(|tilepos| (tilepos.y, tilepos.x));
+        key.dedup();
+
+        if !self.flow_fields.contains_key(&key) && self.flow_fields.len() >= MAX_CACHED_FLOW_FIELDS {
+            self.flow_fields.clear();
+        }
+
+        self.flow_fields
+            .entry(key)
+            .or_insert_with_key(|goals| FlowField::new(world, costs, goals))
+    }
+
+    /// Finds a path from `start` to `goal`, one of the tiles of a destination many agents share such as a stockpile
+    /// zone, including both ends.
+    ///
+    /// The way to the destination follows its flow field, so that everyone heading there shares one computation, and
+    /// only the last few steps inside it are searched for. This blocks until the flow field is built.
+    pub fn path_into(
+        &mut self,
+        world: &World,
+        costs: &MovementCosts,
+        start: TilePos,
+        destination: &[TilePos],
+        goal: TilePos,
+    ) -> Option<Vec<TilePos>> {
+        let mut path = self.flow_field(world, costs, destination).path_from(world, start)?;
+        let entry = *path.last()?;
+
+        if entry != goal {
+            path.extend(self.find_path(world, costs, entry, goal)?.into_iter().skip(1));
+        }
+
+        Some(path)
+    }
+
+    /// Forgets every cached path and flow field, and drops the results of searches that are still running.
+    pub fn invalidate(&mut self) {
+        self.paths.clear();
+        self.flow_fields.clear();
+        self.queue.clear();
+        self.pending.clear();
+        self.snapshot = None;
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    fn cache_path(&mut self, start: TilePos, goal: TilePos, path: Option<Vec<TilePos>>) {
+        if self.paths.len() >= MAX_CACHED_PATHS {
+            self.paths.clear();
+        }
+
+        self.pending.remove(&(start, goal));
+        self.paths.insert((start, goal), path);
+    }
+}
+
+/// Labels every walkable tile with the connected region it belongs to.
+///
+/// Two tiles share a region exactly when a path exists between them, so `same_region` answers reachability without
+/// searching. The labels are kept up to date as the `World` changes, re-flooding only the regions that were touched.
+///
+/// # Examples
+///
+/// ```
+/// use bevy_ecs_tilemap::prelude::TilePos;
+/// use bevy_game::navigation::Regions;
+/// use bevy_game::villager::{MovementCosts, EMPTY_TILE_ID};
+/// use bevy_game::worldgen::{World, TILEMAP_SIZE};
+/// use grid_2d::{Coord, Grid, Size};
+///
+/// let costs = MovementCosts::default();
+/// let mut world = World {
+///     tiles: Grid::new_copy(Size::new(TILEMAP_SIZE.x, TILEMAP_SIZE.y), 17),
+/// };
+/// let mut regions = Regions::default();
+/// regions.update(&world, &costs);
+///
+/// let a = TilePos { x: 0, y: 0 };
+/// let b = TilePos { x: 20, y: 0 };
+/// assert!(regions.same_region(a, b));
+///
+/// for y in 0..TILEMAP_SIZE.y {
+///     *world.tiles.get_checked_mut(Coord::new(10, y as i32)) = EMPTY_TILE_ID;
+/// }
+/// regions.update(&world, &costs);
+/// assert!(!regions.same_region(a, b));
+/// ```
+#[derive(Resource)]
+pub struct Regions {
+    labels: Grid<Option<u32>>,
+    /// Which tiles were walkable when the labels were last updated
+    walkable: Grid<bool>,
+    next_label: u32,
+}
+
+impl Default for Regions {
+    fn default() -> Self {
+        let size = Size::new(TILEMAP_SIZE.x, TILEMAP_SIZE.y);
+
+        Self {
+            labels: Grid::new_copy(size, None),
+            walkable: Grid::new_copy(size, false),
+            next_label: 0,
+        }
+    }
+}
+
+impl Regions {
+    /// Returns the region of a tile, or `None` if it cannot be walked on.
+    pub fn region(&self, tilepos: TilePos) -> Option<u32> {
+        self.labels.get(tilepos.to_coord()).copied().flatten()
+    }
+
+    /// Returns true if both tiles are walkable and a path exists between them.
+    pub fn same_region(&self, a: TilePos, b: TilePos) -> bool {
+        self.region(a).is_some() && self.region(a) == self.region(b)
+    }
+
+    /// Relabels the regions around every tile whose walkability changed since the last update.
+    ///
+    /// Returns how many tiles had changed.
+    pub fn update(&mut self, world: &World, costs: &MovementCosts) -> usize {
+        let changed: Vec<TilePos> = world
+            .tiles
+            .enumerate()
+            .zip(self.walkable.iter())
+            .filter(|((_, &value), &was_walkable)| costs.cost(value).is_some() != was_walkable)
+            .map(|((coord, _), _)| TilePos::new(coord.x as u32, coord.y as u32))
+            .collect();
+
+        for tilepos in &changed {
+            let walkable = self.walkable.get_checked_mut(tilepos.to_coord());
+            *walkable = !*walkable;
+
+            // Cleared up front so that no flood below starts from a tile that is about to be removed
+            if !*walkable {
+                *self.labels.get_checked_mut(tilepos.to_coord()) = None;
+            }
+        }
+
+        // Regions flooded during this update already cover everything connected to them
+        let first_fresh_label = self.next_label;
+
+        for &tilepos in &changed {
+            if *self.walkable.get_checked(tilepos.to_coord()) {
+                // The tile may join several regions together
+                if self.region(tilepos).is_none_or(|label| label < first_fresh_label) {
+                    self.flood(world, costs, tilepos);
+                }
+            } else {
+                // Removing the tile may split its region apart
+                for &neighbor in neighbors(&tilepos, costs.diagonal).iter() {
+                    if self.region(neighbor).is_some_and(|label| label < first_fresh_label) {
+                        self.flood(world, costs, neighbor);
+                    }
+                }
+            }
+        }
+
+        changed.len()
+    }
+
+    /// Gives every tile reachable from `start` a new label
+    fn flood(&mut self, world: &World, costs: &MovementCosts, start: TilePos) {
+        let label = self.next_label;
+        self.next_label += 1;
+
+        *self.labels.get_checked_mut(start.to_coord()) = Some(label);
+        let mut frontier = vec![start];
+
+        while let Some(current) = frontier.pop() {
+            for &neighbor in neighbors(&current, costs.diagonal).iter() {
+                if self.region(neighbor) == Some(label) || costs.step_cost(world, current, neighbor).is_none() {
+                    continue;
+                }
+
+                *self.labels.get_checked_mut(neighbor.to_coord()) = Some(label);
+                frontier.push(neighbor);
+            }
+        }
+    }
+}
+
+/// The cost of the cheapest route from every tile to the nearest of a set of goal tiles.
+///
+/// # Examples
+///
+/// ```
+/// use bevy_ecs_tilemap::prelude::TilePos;
+/// use bevy_game::navigation::FlowField;
+/// use bevy_game::villager::{MovementCosts, BASE_MOVE_COST};
+/// use bevy_game::worldgen::{World, TILEMAP_SIZE};
+/// use grid_2d::{Grid, Size};
+///
+/// let world = World {
+///     tiles: Grid::new_copy(Size::new(TILEMAP_SIZE.x, TILEMAP_SIZE.y), 17),
+/// };
+/// let goals = [TilePos { x: 0, y: 0 }, TilePos { x: 10, y: 0 }];
+/// let field = FlowField::new(&world, &MovementCosts::default(), &goals);
+///
+/// assert_eq!(field.distance(TilePos { x: 8, y: 0 }), Some(2 * BASE_MOVE_COST));
+/// assert_eq!(field.path_from(&world, TilePos { x: 8, y: 0 }).unwrap().last(), Some(&goals[1]));
+/// ```
+pub struct FlowField {
+    distances: Grid<Option<u32>>,
+    costs: MovementCosts,
+}
+
+impl FlowField {
+    /// Builds the flow field with a Dijkstra search outwards from the goals.
+    pub fn new(world: &World, costs: &MovementCosts, goals: &[TilePos]) -> Self {
+        let mut distances = Grid::new_copy(Size::new(TILEMAP_SIZE.x, TILEMAP_SIZE.y), None);
+        let mut frontier = BinaryHeap::new();
+
+        for &goal in goals {
+            if let Some(distance) = distances.get_mut(goal.to_coord()) {
+                *distance = Some(0);
+                frontier.push(Reverse((0, goal.x, goal.y)));
+            }
+        }
+
+        while let Some(Reverse((distance, x, y))) = frontier.pop() {
+            let current = TilePos { x, y };
+            if distances.get(current.to_coord()).copied().flatten() != Some(distance) {
+                // A cheaper route to this tile was already expanded
+                continue;
+            }
+
+            for &neighbor in neighbors(&current, costs.diagonal).iter() {
+                // Walking the field goes from `neighbor` onto `current`, so that is the step that has to be paid for
+                let Some(step_cost) = costs.step_cost(world, neighbor, current) else {
+                    continue;
+                };
+
+                let candidate = distance + step_cost;
+                let Some(neighbor_distance) = distances.get_mut(neighbor.to_coord()) else {
+                    continue;
+                };
+
+                if neighbor_distance.is_none_or(|existing| candidate < existing) {
+                    *neighbor_distance = Some(candidate);
+                    frontier.push(Reverse((candidate, neighbor.x, neighbor.y)));
+                }
+            }
+        }
+
+        Self {
+            distances,
+            costs: costs.clone(),
+        }
+    }
+
+    /// Returns the cost of the cheapest route from `tilepos` to a goal, or `None` if no goal can be reached.
+    pub fn distance(&self, tilepos: TilePos) -> Option<u32> {
+        self.distances.get(tilepos.to_coord()).copied().flatten()
+    }
+
+    /// Returns the next tile to step onto when walking from `tilepos` towards the goals.
+    pub fn next_step(&self, world: &World, tilepos: TilePos) -> Option<TilePos> {
+        let distance = self.distance(tilepos)?;
+
+        neighbors(&tilepos, self.costs.diagonal)
+            .iter()
+            .filter_map(|&neighbor| {
+                // Only ever step closer to the goals so that following the field always terminates
+                let neighbor_distance = self.distance(neighbor).filter(|&d| d < distance)?;
+                let total = self.costs.step_cost(world, tilepos, neighbor)? + neighbor_distance;
+                Some((total, neighbor))
+            })
+            .min_by_key(|&(total, neighbor)| (total, neighbor.y, neighbor.x))
+            .map(|(_, neighbor)| neighbor)
+    }
+
+    /// Follows the field from `start` to the nearest goal, returning the path including both ends.
+    pub fn path_from(&self, world: &World, start: TilePos) -> Option<Vec<TilePos>> {
+        let mut path = vec![start];
+        let mut current = start;
+
+        while self.distance(current)? > 0 {
+            current = self.next_step(world, current)?;
+            path.push(current);
+        }
+
+        Some(path)
+    }
+}
+
+/// Finds a path on the terrain of `graph`, planning on the graph itself only when the ends are far apart
+fn search(graph: &HierarchicalGraph, start: TilePos, goal: TilePos) -> Option<Vec<TilePos>> {
+    let distance = start.x.abs_diff(goal.x).max(start.y.abs_diff(goal.y));
+
+    if distance > HIERARCHICAL_SEARCH_DISTANCE {
+        graph.find_path(start, goal)
+    } else {
+        find_path(graph.world(), graph.costs(), start, goal)
+    }
+}
+
+fn neighbors(tilepos: &TilePos, diagonal: DiagonalMovement) -> Neighbors<TilePos> {
+    Neighbors::get_square_neighboring_positions(tilepos, &TILEMAP_SIZE, diagonal != DiagonalMovement::Never)
+}
+
+fn invalidate_pathfinder_system(world: Res<World>, costs: Res<MovementCosts>, mut pathfinder: ResMut<Pathfinder>) {
+    if world.is_changed() || costs.is_changed() {
+        pathfinder.invalidate();
+    }
+}
+
+fn spawn_path_searches_system(graph: Option<Res<HierarchicalGraph>>, mut pathfinder: ResMut<Pathfinder>) {
+    // The graph is inserted by a command the first time the world is ready, so it can be a step late
+    let Some(graph) = graph else {
+        return;
+    };
+
+    let pathfinder = &mut *pathfinder;
+    let snapshot = pathfinder
+        .snapshot
+        .get_or_insert_with(|| Arc::new(graph.clone()))
+        .clone();
+
+    for _ in 0..pathfinder.searches_per_frame {
+        let Some((start, goal)) = pathfinder.queue.pop_front() else {
+            break;
+        };
+
+        if pathfinder.blocking {
+            let path = search(&snapshot, start, goal);
+            pathfinder.cache_path(start, goal, path);
+            continue;
+        }
+
+        let snapshot = snapshot.clone();
+        let sender = pathfinder.sender.clone();
+        let generation = pathfinder.generation;
+
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                let path = search(&snapshot, start, goal);
+
+                // The receiver lives as long as the app, so this only fails while shutting down
+                let _ = sender.send(PathResult {
+                    generation,
+                    start,
+                    goal,
+                    path,
+                });
+            })
+            .detach();
+    }
+}
+
+fn receive_paths_system(mut pathfinder: ResMut<Pathfinder>) {
+    let results: Vec<PathResult> = pathfinder.receiver.lock().unwrap().try_iter().collect();
+
+    for result in results {
+        if result.generation == pathfinder.generation {
+            pathfinder.cache_path(result.start, result.goal, result.path);
+        }
+    }
+}
+
+/// Keeps the `HierarchicalGraph` in step with the terrain, only rebuilding it from scratch when the costs change
+fn update_hierarchy_system(
+    mut commands: Commands,
+    world: Res<World>,
+    costs: Res<MovementCosts>,
+    graph: Option<ResMut<HierarchicalGraph>>,
+) {
+    match graph {
+        Some(mut graph) if !costs.is_changed() => {
+            if world.is_changed() {
+                graph.update(&world);
+            }
+        }
+        _ => commands.insert_resource(HierarchicalGraph::new(&world, &costs)),
+    }
+}
+
+fn update_regions_system(world: Res<World>, costs: Res<MovementCosts>, mut regions: ResMut<Regions>) {
+    if costs.is_changed() {
+        *regions = Regions::default();
+    }
+
+    if world.is_changed() || costs.is_changed() {
+        regions.update(&world, &costs);
+    }
+}
@@ -0,0 +1,593 @@
+use crate::agent::{held_target, BUSH_TO_GATHER};
+use crate::behavior::BehaviorAppExt;
+use crate::blackboard::{Blackboard, BlackboardKey, Blackboards, ColonyBlackboard};
+use crate::ext::{TilePosExt, Vec2Ext};
+use crate::items::Inventory;
+use crate::navigation::{PathQuery, Pathfinder, Regions};
+use crate::planner::{sense, Fact, Goal, GoapAction, PlannerAppExt, PursueGoal};
+use crate::reservations::{
+    Capacity, ReleaseReason, ReleaseReservation, ReservationLedger, ReservationRequest, ReservationRequestBuilder,
+    Reserved,
+};
+use crate::states::States::Play;
+use crate::stockpile::StockpileCell;
+use crate::villager::{Movement, PathNotFound};
+use crate::worldgen::TILEMAP_TILE_SIZE;
+use crate::SimulationSet;
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::TilePos;
+use big_brain::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// How much of a full stomach is used up every second
+const HUNGER_DECAY: f32 = 1.0 / 300.0;
+
+/// How much energy is used up every second while awake
+const ENERGY_DECAY: f32 = 1.0 / 480.0;
+
+/// How much mood is lost every second while all other needs are met, going hungry or tired makes it fall faster
+const MOOD_DECAY: f32 = 1.0 / 600.0;
+
+/// How much hunger is restored every second while eating
+const EAT_RATE: f32 = 1.0 / 4.0;
+
+/// How much energy is restored every second while sleeping on the ground
+const SLEEP_RATE: f32 = 1.0 / 30.0;
+
+/// Sleeping in a bed restores energy this many times faster than on the ground
+const BED_SLEEP_MULTIPLIER: f32 = 2.0;
+
+/// How close, in world units, a bed has to be for a villager to sleep in it
+const BED_REACH: f32 = 16.0;
+
+/// How much mood is restored every second while relaxing
+const RELAX_RATE: f32 = 1.0 / 10.0;
+
+/// Whether the agent has eaten its fill
+pub const FED: Fact = BlackboardKey::new("fed");
+
+/// Whether the agent carries something it can eat
+pub const CARRYING_FOOD: Fact = BlackboardKey::new("carrying_food");
+
+/// Whether any stockpile holds something edible, shared on the `ColonyBlackboard`
+pub const FOOD_STOCKPILED: Fact = BlackboardKey::new("food_stockpiled");
+
+pub struct NeedsPlugin;
+
+impl Plugin for NeedsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_scorer("Hunger", HungerScorer)
+            .register_scorer("Fatigue", FatigueScorer)
+            .register_scorer("Mood", MoodScorer)
+            .register_action("Eat", EatAction::default())
+            .register_action("Sleep", SleepAction::default())
+            .register_action("Relax", RelaxAction)
+            .register_action("Feed", PursueGoal::new(Goal::new("Feed").with(FED, true)))
+            .register_goap_action(
+                GoapAction::new("EatCarried", 1, EatAction::default())
+                    .requires(CARRYING_FOOD, true)
+                    .causes(FED, true),
+            )
+            .register_goap_action(
+                GoapAction::new("EatStockpiled", 2, EatAction::default())
+                    .requires(FOOD_STOCKPILED, true)
+                    .causes(FED, true),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    (eat_action_system, sleep_action_system, relax_action_system)
+                        .chain()
+                        .in_set(SimulationSet::Needs),
+                    (hunger_scorer_system, fatigue_scorer_system, mood_scorer_system).in_set(BigBrainSet::Scorers),
+                    sense_food_system.in_set(SimulationSet::Sense),
+                )
+                    .run_if(in_state(Play)),
+            )
+            .add_systems(
+                FixedUpdate,
+                decay_needs_system.in_set(SimulationSet::Settle).run_if(in_state(Play)),
+            );
+    }
+}
+
+/// How well a villager's needs are met, each from 0.0 (desperate) to 1.0 (fully satisfied)
+#[derive(Clone, Component, Debug, Deserialize, Serialize)]
+pub struct Needs {
+    pub hunger: f32,
+    pub energy: f32,
+    pub mood: f32,
+}
+
+impl Default for Needs {
+    fn default() -> Self {
+        Self {
+            hunger: 1.0,
+            energy: 1.0,
+            mood: 1.0,
+        }
+    }
+}
+
+/// Marks somewhere a villager can sleep more soundly than on the ground
+#[derive(Component)]
+pub struct Bed;
+
+/// Draws the beds villagers sleep in
+pub struct NeedsRenderPlugin;
+
+impl Plugin for NeedsRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, draw_beds_system.run_if(in_state(Play)));
+    }
+}
+
+/// Spawns a bed for a single villager at a tile
+pub fn spawn_bed(commands: &mut Commands, tilepos: TilePos) -> Entity {
+    commands
+        .spawn((
+            Name::new("Bed"),
+            Bed,
+            Capacity(1),
+            tilepos,
+            TransformBundle::from(Transform::from_translation(tilepos.to_world_space().extend(0.0))),
+        ))
+        .id()
+}
+
+fn decay_needs_system(time: Res<Time>, mut query: Query<(&mut Needs, Has<Sleeping>)>) {
+    let delta = time.delta_seconds();
+
+    for (mut needs, sleeping) in &mut query {
+        needs.hunger = (needs.hunger - HUNGER_DECAY * delta).max(0.0);
+
+        if !sleeping {
+            needs.energy = (needs.energy - ENERGY_DECAY * delta).max(0.0);
+        }
+
+        let distress = (1.0 - needs.hunger) + (1.0 - needs.energy);
+        needs.mood = (needs.mood - MOOD_DECAY * (1.0 + distress) * delta).max(0.0);
+    }
+}
+
+/// Keeps `FED` and `CARRYING_FOOD` up to date on every agent's blackboard and `FOOD_STOCKPILED` on the colony's
+fn sense_food_system(
+    cells: Query<&StockpileCell>,
+    mut colony: ResMut<ColonyBlackboard>,
+    mut agents: Query<(&mut Blackboard, &Needs, &Inventory)>,
+) {
+    let stockpiled = cells.iter().any(|cell| cell.edible().is_some());
+    sense(&mut colony.0, FOOD_STOCKPILED, stockpiled);
+
+    for (mut blackboard, needs, inventory) in &mut agents {
+        sense(&mut blackboard, FED, needs.hunger >= 1.0);
+        sense(&mut blackboard, CARRYING_FOOD, inventory.edible().is_some());
+    }
+}
+
+#[derive(Clone, Component, Debug, ScorerBuilder)]
+pub struct HungerScorer;
+
+/// Scores how hungry the actor is, but only while there is food to eat or a bush it could gather some from
+pub fn hunger_scorer_system(
+    blackboards: Blackboards,
+    needs: Query<&Needs>,
+    mut query: Query<(&Actor, &mut Score), With<HungerScorer>>,
+) {
+    for (Actor(actor), mut score) in &mut query {
+        let food = [CARRYING_FOOD, FOOD_STOCKPILED, BUSH_TO_GATHER]
+            .into_iter()
+            .any(|fact| blackboards.get(*actor, fact) == Some(true));
+
+        match needs.get(*actor) {
+            Ok(needs) if food => score.set(1.0 - needs.hunger),
+            _ => score.set(0.0),
+        }
+    }
+}
+
+#[derive(Clone, Component, Debug, ScorerBuilder)]
+pub struct FatigueScorer;
+
+pub fn fatigue_scorer_system(needs: Query<&Needs>, mut query: Query<(&Actor, &mut Score), With<FatigueScorer>>) {
+    for (Actor(actor), mut score) in &mut query {
+        score.set(needs.get(*actor).map_or(0.0, |needs| 1.0 - needs.energy));
+    }
+}
+
+#[derive(Clone, Component, Debug, ScorerBuilder)]
+pub struct MoodScorer;
+
+pub fn mood_scorer_system(needs: Query<&Needs>, mut query: Query<(&Actor, &mut Score), With<MoodScorer>>) {
+    for (Actor(actor), mut score) in &mut query {
+        score.set(needs.get(*actor).map_or(0.0, |needs| 1.0 - needs.mood));
+    }
+}
+
+/// Eats until full, preferring food the actor is carrying over food in the stockpiles.
+///
+/// Food in a stockpile is eaten at the nearest cell holding some, which is reserved before walking there.
+#[derive(Clone, Component, Debug, Default, ActionBuilder)]
+pub struct EatAction {
+    cell: Option<(Entity, TilePos)>,
+    eating: bool,
+}
+
+pub fn eat_action_system(
+    time: Res<Time>,
+    mut pathfinder: ResMut<Pathfinder>,
+    regions: Res<Regions>,
+    ledger: Res<ReservationLedger>,
+    mut cells: Query<(Entity, &TilePos, &mut StockpileCell, Has<Reserved>)>,
+    mut agents: Query<(&mut Needs, &mut Movement, &mut Inventory, &Transform)>,
+    mut action_query: Query<(Entity, &Actor, &mut ActionState, &mut EatAction, &ActionSpan)>,
+    mut reservation_request_writer: EventWriter<ReservationRequest>,
+    mut release_writer: EventWriter<ReleaseReservation>,
+    mut path_not_found_writer: EventWriter<PathNotFound>,
+) {
+    for (action, actor, mut action_state, mut eat, span) in &mut action_query {
+        let _guard = span.span().enter();
+
+        let Ok((mut needs, mut movement, mut inventory, transform)) = agents.get_mut(actor.0) else {
+            *action_state = ActionState::Failure;
+            continue;
+        };
+
+        let position = transform.translation.xy().to_tilepos();
+
+        match *action_state {
+            ActionState::Requested => {
+                if let Some(item) = inventory.edible() {
+                    inventory.remove(item, 1);
+                    movement.path.clear();
+                    eat.eating = true;
+                    *action_state = ActionState::Executing;
+                    continue;
+                }
+
+                let Some((cell_entity, goal)) = eat.cell else {
+                    // A cell still held from before the colony was loaded is eaten from first
+                    let held = ledger
+                        .reservations_of(actor.0)
+                        .filter_map(|reservation| cells.get(reservation.target.entity()?).ok())
+                        .find(|(_, _, cell, _)| cell.edible().is_some());
+                    let nearest = held.or_else(|| {
+                        cells
+                            .iter()
+                            .filter(|(_, &tilepos, cell, reserved)| {
+                                !reserved && cell.edible().is_some() && regions.same_region(position, tilepos)
+                            })
+                            .min_by_key(|(_, &tilepos, _, _)| {
+                                position.x.abs_diff(tilepos.x) + position.y.abs_diff(tilepos.y)
+                            })
+                    });
+
+                    let Some((cell_entity, &tilepos, _, _)) = nearest else {
+                        *action_state = ActionState::Failure;
+                        continue;
+                    };
+
+                    reservation_request_writer.send(
+                        ReservationRequestBuilder::default()
+                            .requester(actor.0)
+                            .action(action)
+                            .target(cell_entity)
+                            .build()
+                            .unwrap(),
+                    );
+                    eat.cell = Some((cell_entity, tilepos));
+                    continue;
+                };
+
+                if !ledger.is_reserved_by(cell_entity, actor.0) {
+                    if cells.get(cell_entity).map_or(true, |(_, _, _, reserved)| reserved) {
+                        // Someone else got there first, pick another cell next frame
+                        eat.cell = None;
+                    }
+                    // Otherwise still waiting for the reservation to go through
+                    continue;
+                }
+
+                match pathfinder.query(position, goal) {
+                    PathQuery::Found(mut path) => {
+                        if path.first() == Some(&position) {
+                            path.remove(0);
+                        }
+
+                        movement.path = path;
+                        *action_state = ActionState::Executing;
+                    }
+                    PathQuery::NotFound => {
+                        path_not_found_writer.send(PathNotFound {
+                            agent: actor.0,
+                            start: position,
+                            goal,
+                        });
+                        release_cell(&mut release_writer, actor.0, &mut eat, ReleaseReason::Failed);
+                        *action_state = ActionState::Failure;
+                    }
+                    PathQuery::Pending => {}
+                }
+            }
+            ActionState::Executing if eat.eating => {
+                needs.hunger = (needs.hunger + EAT_RATE * time.delta_seconds()).min(1.0);
+
+                if needs.hunger >= 1.0 {
+                    *action_state = ActionState::Success;
+                }
+            }
+            ActionState::Executing => {
+                let Some((cell_entity, goal)) = eat.cell else {
+                    *action_state = ActionState::Failure;
+                    continue;
+                };
+
+                if !movement.path.is_empty() || position != goal {
+                    // Movement should be handled by the movement system
+                    continue;
+                }
+
+                let taken = cells
+                    .get_mut(cell_entity)
+                    .ok()
+                    .filter(|(_, _, cell, _)| cell.edible().is_some())
+                    .map_or(0, |(_, _, mut cell, _)| cell.take(1));
+
+                if taken == 0 {
+                    // Eaten or hauled away while walking over
+                    release_cell(&mut release_writer, actor.0, &mut eat, ReleaseReason::Failed);
+                    *action_state = ActionState::Failure;
+                    continue;
+                }
+
+                release_cell(&mut release_writer, actor.0, &mut eat, ReleaseReason::Done);
+                eat.eating = true;
+            }
+            ActionState::Cancelled => {
+                release_cell(&mut release_writer, actor.0, &mut eat, ReleaseReason::Failed);
+                movement.path.clear();
+                *action_state = ActionState::Failure;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Forgets the stockpile cell, giving up the reservation on it
+fn release_cell(
+    release_writer: &mut EventWriter<ReleaseReservation>,
+    agent: Entity,
+    eat: &mut EatAction,
+    reason: ReleaseReason,
+) {
+    if let Some((cell, _)) = eat.cell.take() {
+        release_writer.send(ReleaseReservation {
+            owner: agent,
+            target: Some(cell.into()),
+            reason,
+        });
+    }
+}
+
+/// Sleeps until fully rested, walking to the nearest free bed first and lying down on the ground when there is none
+#[derive(Clone, Component, Debug, Default, ActionBuilder)]
+pub struct SleepAction {
+    bed: Option<(Entity, TilePos)>,
+    /// Whether the path to the bed was set
+    walking: bool,
+}
+
+/// Tag for villagers that are asleep, who do not lose energy
+#[derive(Component)]
+pub struct Sleeping;
+
+pub fn sleep_action_system(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut pathfinder: ResMut<Pathfinder>,
+    regions: Res<Regions>,
+    ledger: Res<ReservationLedger>,
+    beds: Query<&TilePos, With<Bed>>,
+    free_beds: Query<(Entity, &TilePos), (With<Bed>, Without<Reserved>)>,
+    mut agents: Query<(&mut Needs, &mut Movement, &Transform, Has<Sleeping>), Without<Bed>>,
+    mut action_query: Query<(Entity, &Actor, &mut ActionState, &mut SleepAction, &ActionSpan)>,
+    mut reservation_request_writer: EventWriter<ReservationRequest>,
+    mut release_writer: EventWriter<ReleaseReservation>,
+    mut path_not_found_writer: EventWriter<PathNotFound>,
+) {
+    for (action, actor, mut action_state, mut sleep, span) in &mut action_query {
+        let _guard = span.span().enter();
+
+        let Ok((mut needs, mut movement, transform, asleep)) = agents.get_mut(actor.0) else {
+            *action_state = ActionState::Failure;
+            continue;
+        };
+
+        let position = transform.translation.xy().to_tilepos();
+
+        match *action_state {
+            ActionState::Requested => {
+                movement.path.clear();
+
+                // A bed still held from before the colony was loaded is slept in first
+                let nearest = held_target(&ledger, actor.0, &beds).or_else(|| {
+                    free_beds
+                        .iter()
+                        .filter(|(_, &tilepos)| regions.same_region(position, tilepos))
+                        .min_by_key(|(_, &tilepos)| position.x.abs_diff(tilepos.x) + position.y.abs_diff(tilepos.y))
+                        .map(|(bed, &tilepos)| (bed, tilepos))
+                });
+
+                match nearest {
+                    Some((bed, tilepos)) => {
+                        reservation_request_writer.send(
+                            ReservationRequestBuilder::default()
+                                .requester(actor.0)
+                                .action(action)
+                                .target(bed)
+                                .build()
+                                .unwrap(),
+                        );
+                        sleep.bed = Some((bed, tilepos));
+                    }
+                    None => {
+                        commands.entity(actor.0).insert(Sleeping);
+                    }
+                }
+
+                *action_state = ActionState::Executing;
+            }
+            ActionState::Executing if !asleep => {
+                let Some((bed, tilepos)) = sleep.bed else {
+                    commands.entity(actor.0).insert(Sleeping);
+                    continue;
+                };
+
+                if !ledger.is_reserved_by(bed, actor.0) {
+                    // Someone else got the bed first, or it is gone
+                    debug!("Lost the bed to sleep in");
+                    fall_asleep_on_the_ground(&mut commands, actor.0, &mut sleep, &mut movement, &mut release_writer);
+                    continue;
+                }
+
+                if sleep.walking {
+                    if movement.path.is_empty() {
+                        commands.entity(actor.0).insert(Sleeping);
+                    }
+                    continue;
+                }
+
+                match pathfinder.query(position, tilepos) {
+                    PathQuery::Found(mut path) => {
+                        if path.first() == Some(&position) {
+                            path.remove(0);
+                        }
+
+                        movement.path = path;
+                        sleep.walking = true;
+                    }
+                    PathQuery::NotFound => {
+                        path_not_found_writer.send(PathNotFound {
+                            agent: actor.0,
+                            start: position,
+                            goal: tilepos,
+                        });
+                        fall_asleep_on_the_ground(
+                            &mut commands,
+                            actor.0,
+                            &mut sleep,
+                            &mut movement,
+                            &mut release_writer,
+                        );
+                    }
+                    PathQuery::Pending => {}
+                }
+            }
+            ActionState::Executing => {
+                let in_bed = sleep.bed.is_some_and(|(bed, tilepos)| {
+                    ledger.is_reserved_by(bed, actor.0)
+                        && tilepos.to_world_space().distance(transform.translation.xy()) <= BED_REACH
+                });
+                let rate = if in_bed {
+                    SLEEP_RATE * BED_SLEEP_MULTIPLIER
+                } else {
+                    SLEEP_RATE
+                };
+                needs.energy = (needs.energy + rate * time.delta_seconds()).min(1.0);
+
+                if needs.energy >= 1.0 {
+                    commands.entity(actor.0).remove::<Sleeping>();
+                    release_bed(&mut release_writer, actor.0, &mut sleep, ReleaseReason::Done);
+                    *action_state = ActionState::Success;
+                }
+            }
+            ActionState::Cancelled => {
+                commands.entity(actor.0).remove::<Sleeping>();
+                release_bed(&mut release_writer, actor.0, &mut sleep, ReleaseReason::Failed);
+                movement.path.clear();
+                *action_state = ActionState::Failure;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Gives up on the bed and sleeps wherever the villager stands
+fn fall_asleep_on_the_ground(
+    commands: &mut Commands,
+    agent: Entity,
+    sleep: &mut SleepAction,
+    movement: &mut Movement,
+    release_writer: &mut EventWriter<ReleaseReservation>,
+) {
+    release_bed(release_writer, agent, sleep, ReleaseReason::Failed);
+    movement.path.clear();
+    commands.entity(agent).insert(Sleeping);
+}
+
+/// Forgets the bed, giving up the reservation on it
+fn release_bed(
+    release_writer: &mut EventWriter<ReleaseReservation>,
+    agent: Entity,
+    sleep: &mut SleepAction,
+    reason: ReleaseReason,
+) {
+    sleep.walking = false;
+    if let Some((bed, _)) = sleep.bed.take() {
+        release_writer.send(ReleaseReservation {
+            owner: agent,
+            target: Some(bed.into()),
+            reason,
+        });
+    }
+}
+
+/// Takes a break on the spot until in good spirits again
+#[derive(Clone, Component, Debug, ActionBuilder)]
+pub struct RelaxAction;
+
+pub fn relax_action_system(
+    time: Res<Time>,
+    mut agents: Query<(&mut Needs, &mut Movement)>,
+    mut action_query: Query<(&Actor, &mut ActionState, &ActionSpan), With<RelaxAction>>,
+) {
+    for (actor, mut action_state, span) in &mut action_query {
+        let _guard = span.span().enter();
+
+        let Ok((mut needs, mut movement)) = agents.get_mut(actor.0) else {
+            *action_state = ActionState::Failure;
+            continue;
+        };
+
+        match *action_state {
+            ActionState::Requested => {
+                movement.path.clear();
+                *action_state = ActionState::Executing;
+            }
+            ActionState::Executing => {
+                needs.mood = (needs.mood + RELAX_RATE * time.delta_seconds()).min(1.0);
+
+                if needs.mood >= 1.0 {
+                    *action_state = ActionState::Success;
+                }
+            }
+            ActionState::Cancelled => {
+                *action_state = ActionState::Failure;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw_beds_system(mut gizmos: Gizmos, beds: Query<&TilePos, With<Bed>>) {
+    let tile_size = Vec2::from(TILEMAP_TILE_SIZE);
+
+    for tilepos in beds.iter() {
+        gizmos.rect_2d(
+            tilepos.to_world_space(),
+            0.0,
+            tile_size * 0.75,
+            Color::srgb_u8(211, 54, 130),
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use crate::agent::{harvest_bush, Bush, GATHER_SECONDS};
+use crate::animation::GatheringTag;
+use crate::behavior::BehaviorAppExt;
+use crate::blackboard::ColonyBlackboard;
+use crate::ext::Vec2Ext;
+use crate::items::Inventory;
+use crate::navigation::{PathQuery, Pathfinder, Regions};
+use crate::reservations::{
+    PreemptReservation, PreemptReservationBuilder, ReleaseReason, ReleaseReservation, RemoveReservation,
+    ReservationLedger, ReservationRequest, ReservationRequestBuilder, ReservationTarget,
+};
+use crate::states::States::Play;
+use crate::villager::{Movement, PathBlocked, PathNotFound};
+use crate::SimulationSet;
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::TilePos;
+use big_brain::prelude::*;
+use std::collections::HashSet;
+
+/// How far, in tiles, from where a villager was ordered to it may stand when others already stand there
+const STANDING_SPOT_RADIUS: i32 = 2;
+
+pub struct OrdersPlugin;
+
+impl Plugin for OrdersPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_scorer("Order", OrderScorer)
+            .register_action("FollowOrder", FollowOrderAction::default())
+            .add_systems(
+                FixedUpdate,
+                (
+                    follow_order_action_system.in_set(SimulationSet::Orders),
+                    abandon_blocked_orders_system.in_set(SimulationSet::Prepare),
+                    order_scorer_system.in_set(BigBrainSet::Scorers),
+                )
+                    .run_if(in_state(Play)),
+            );
+    }
+}
+
+/// A direct order from the player, which a villager carries out ahead of anything its thinker would pick
+#[derive(Clone, Component, Copy, Debug, PartialEq)]
+pub enum Order {
+    /// Walk to a tile, or the closest free tile to it when other villagers were sent there too
+    MoveTo(TilePos),
+    /// Gather a bush right away, designating it if it was not and taking it over from whoever had reserved it
+    Gather(Entity),
+}
+
+#[derive(Clone, Component, Debug, ScorerBuilder)]
+pub struct OrderScorer;
+
+/// Scores the highest possible value while the actor has an order, so it wins over every need and job
+pub fn order_scorer_system(orders: Query<(), With<Order>>, mut query: Query<(&Actor, &mut Score), With<OrderScorer>>) {
+    for (Actor(actor), mut score) in &mut query {
+        score.set(if orders.contains(*actor) { 1.0 } else { 0.0 });
+    }
+}
+
+/// Carries out the actor's `Order`, removing it once it is done or cannot be done
+#[derive(Clone, Component, Debug, Default, ActionBuilder)]
+pub struct FollowOrderAction {
+    goal: Option<TilePos>,
+    gathering: Option<Timer>,
+}
+
+/// Returns the closest tile to `goal` that `agent` can reach from `start` and nobody else has reserved or claimed
+fn standing_spot(
+    agent: Entity,
+    start: TilePos,
+    goal: TilePos,
+    ledger: &ReservationLedger,
+    regions: &Regions,
+    claimed: &HashSet<TilePos>,
+) -> Option<TilePos> {
+    (0..=STANDING_SPOT_RADIUS).find_map(|radius| {
+        (-radius..=radius)
+            .flat_map(|dx| (-radius..=radius).map(move |dy| (dx, dy)))
+            .filter(|(dx, dy)| dx.abs().max(dy.abs()) == radius)
+            .filter_map(|(dx, dy)| {
+                let x = u32::try_from(goal.x as i32 + dx).ok()?;
+                let y = u32::try_from(goal.y as i32 + dy).ok()?;
+                Some(TilePos { x, y })
+            })
+            .find(|&tilepos| {
+                regions.same_region(start, tilepos)
+                    && !claimed.contains(&tilepos)
+                    && ledger.who_reserved(tilepos).all(|owner| owner == agent)
+            })
+    })
+}
+
+pub fn follow_order_action_system(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut pathfinder: ResMut<Pathfinder>,
+    regions: Res<Regions>,
+    ledger: Res<ReservationLedger>,
+    mut colony: ResMut<ColonyBlackboard>,
+    mut agents: Query<(Ref<Order>, &Transform, &mut Movement, &mut Inventory)>,
+    bushes: Query<&TilePos, With<Bush>>,
+    mut action_query: Query<(Entity, &Actor, &mut ActionState, &mut FollowOrderAction, &ActionSpan)>,
+    mut path_not_found_writer: EventWriter<PathNotFound>,
+    mut remove_reservation_writer: EventWriter<RemoveReservation>,
+    mut reservation_request_writer: EventWriter<ReservationRequest>,
+    mut preempt_writer: EventWriter<PreemptReservation>,
+    mut release_writer: EventWriter<ReleaseReservation>,
+) {
+    // Spots claimed this frame, before their reservations have gone through
+    let mut claimed = HashSet::new();
+
+    for (action, actor, mut action_state, mut follow, span) in &mut action_query {
+        let _guard = span.span().enter();
+
+        let Ok((order, transform, mut movement, mut inventory)) = agents.get_mut(actor.0) else {
+            *action_state = ActionState::Failure;
+            continue;
+        };
+
+        let start = transform.translation.xy().to_tilepos();
+
+        match *action_state {
+            ActionState::Requested => {
+                let goal = match *order {
+                    Order::MoveTo(tilepos) => {
+                        let Some(spot) = standing_spot(actor.0, start, tilepos, &ledger, &regions, &claimed) else {
+                            // Every spot nearby is taken or cut off, and the goal may be held by someone else
+                            debug!("Found nowhere to stand near {:?}", tilepos);
+                            commands.entity(actor.0).remove::<Order>();
+                            *action_state = ActionState::Failure;
+                            continue;
+                        };
+
+                        claimed.insert(spot);
+                        if !ledger.is_claimed_by(spot, actor.0) {
+                            reservation_request_writer.send(
+                                ReservationRequestBuilder::default()
+                                    .requester(actor.0)
+                                    .action(action)
+                                    .target(spot)
+                                    .build()
+                                    .unwrap(),
+                            );
+                        }
+
+                        spot
+                    }
+                    Order::Gather(bush) => {
+                        let Ok(&tilepos) = bushes.get(bush) else {
+                            // The bush was gathered by someone else before the order could be carried out
+                            commands.entity(actor.0).remove::<Order>();
+                            *action_state = ActionState::Failure;
+                            continue;
+                        };
+
+                        if !ledger.is_claimed_by(bush, actor.0) {
+                            // Whoever was working on the bush has to find something else to do
+                            preempt_writer.send(
+                                PreemptReservationBuilder::default()
+                                    .requester(actor.0)
+                                    .action(action)
+                                    .target(bush)
+                                    .build()
+                                    .unwrap(),
+                            );
+                        }
+
+                        tilepos
+                    }
+                };
+
+                match pathfinder.query(start, goal) {
+                    PathQuery::Found(mut path) => {
+                        if path.first() == Some(&start) {
+                            path.remove(0);
+                        }
+
+                        movement.path = path;
+                        follow.goal = Some(goal);
+                        *action_state = ActionState::Executing;
+                    }
+                    PathQuery::NotFound => {
+                        path_not_found_writer.send(PathNotFound {
+                            agent: actor.0,
+                            start,
+                            goal,
+                        });
+
+                        // The bush may have been preempted this very frame, so give it up explicitly
+                        release_writer.send(ReleaseReservation {
+                            owner: actor.0,
+                            target: None,
+                            reason: ReleaseReason::Failed,
+                        });
+
+                        commands.entity(actor.0).remove::<Order>();
+                        *action_state = ActionState::Failure;
+                    }
+                    PathQuery::Pending => {}
+                }
+            }
+            ActionState::Executing => {
+                if order.is_changed() {
+                    // The player gave a new order before this one was done
+                    if let Some(spot) = follow.goal.take() {
+                        release_writer.send(ReleaseReservation {
+                            owner: actor.0,
+                            target: Some(spot.into()),
+                            reason: ReleaseReason::Failed,
+                        });
+                    }
+                    follow.gathering = None;
+                    commands.entity(actor.0).remove::<GatheringTag>();
+                    *action_state = ActionState::Requested;
+                    continue;
+                }
+
+                let arrived = movement.path.is_empty() && Some(start) == follow.goal;
+                if !arrived {
+                    // Movement is handled by the movement system
+                    continue;
+                }
+
+                match *order {
+                    Order::MoveTo(_) => {
+                        release_writer.send(ReleaseReservation {
+                            owner: actor.0,
+                            target: follow.goal.take().map(Into::into),
+                            reason: ReleaseReason::Done,
+                        });
+                        commands.entity(actor.0).remove::<Order>();
+                        *action_state = ActionState::Success;
+                    }
+                    Order::Gather(bush) => {
+                        let held = ledger.is_reserved_by(bush, actor.0);
+                        let (Ok(&tilepos), true) = (bushes.get(bush), held) else {
+                            // The bush is gone or its designation was cancelled while the villager was on its way
+                            commands.entity(actor.0).remove::<(Order, GatheringTag)>();
+                            follow.gathering = None;
+                            *action_state = ActionState::Failure;
+                            continue;
+                        };
+
+                        let timer = follow.gathering.get_or_insert_with(|| {
+                            commands.entity(actor.0).insert(GatheringTag {});
+                            Timer::from_seconds(GATHER_SECONDS, TimerMode::Once)
+                        });
+
+                        if timer.tick(time.delta()).finished() {
+                            harvest_bush(
+                                &mut commands,
+                                bush,
+                                tilepos,
+                                &mut inventory,
+                                &mut colony,
+                                &mut remove_reservation_writer,
+                            );
+                            commands.entity(actor.0).remove::<(Order, GatheringTag)>();
+                            release_writer.send(ReleaseReservation {
+                                owner: actor.0,
+                                target: Some(bush.into()),
+                                reason: ReleaseReason::Done,
+                            });
+                            follow.gathering = None;
+                            *action_state = ActionState::Success;
+                        }
+                    }
+                }
+            }
+            ActionState::Cancelled => {
+                if let Some(target) = order_target(*order, follow.goal) {
+                    release_writer.send(ReleaseReservation {
+                        owner: actor.0,
+                        target: Some(target),
+                        reason: ReleaseReason::Failed,
+                    });
+                }
+                follow.gathering = None;
+                commands.entity(actor.0).remove::<(Order, GatheringTag)>();
+                *action_state = ActionState::Failure;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns what the order had reserved: the spot to stand on, or the bush to gather
+fn order_target(order: Order, spot: Option<TilePos>) -> Option<ReservationTarget> {
+    match order {
+        Order::MoveTo(_) => spot.map(Into::into),
+        Order::Gather(bush) => Some(bush.into()),
+    }
+}
+
+/// Drops the orders of villagers whose path became blocked, since the action carrying them out is failed without
+/// getting the chance to clean up
+fn abandon_blocked_orders_system(
+    mut commands: Commands,
+    mut path_blocked: EventReader<PathBlocked>,
+    orders: Query<&Order>,
+    actions: Query<(&Actor, &FollowOrderAction)>,
+    mut release_writer: EventWriter<ReleaseReservation>,
+) {
+    for event in path_blocked.read() {
+        let Ok(&order) = orders.get(event.agent) else {
+            continue;
+        };
+
+        let spot = actions
+            .iter()
+            .find_map(|(actor, follow)| (actor.0 == event.agent).then_some(follow.goal)?);
+        if let Some(target) = order_target(order, spot) {
+            release_writer.send(ReleaseReservation {
+                owner: event.agent,
+                target: Some(target),
+                reason: ReleaseReason::Failed,
+            });
+        }
+
+        debug!("{:?} gave up on {:?} because its path is blocked", event.agent, order);
+        commands.entity(event.agent).remove::<(Order, GatheringTag)>();
+    }
+}
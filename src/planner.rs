@@ -0,0 +1,365 @@
+use crate::blackboard::{Blackboard, BlackboardKey, Blackboards};
+use crate::SimulationSet;
+use bevy::prelude::*;
+use big_brain::actions::spawn_action;
+use big_brain::prelude::*;
+use extend::ext;
+use pathfinding::prelude::dijkstra;
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// A true or false fact about an agent or the colony, as kept on its blackboards
+pub type Fact = BlackboardKey<bool>;
+
+/// Executes `PursueGoal` actions, the planner actions they can use are registered with `App::register_goap_action`.
+pub struct PlannerPlugin;
+
+impl Plugin for PlannerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GoapActions>()
+            .add_systems(FixedUpdate, pursue_goal_system.in_set(SimulationSet::Plan));
+    }
+}
+
+/// Writes a fact to a blackboard, leaving the blackboard untouched when it already holds that value so that sensing
+/// every frame does not bump its revision.
+pub fn sense(blackboard: &mut Blackboard, fact: Fact, value: bool) {
+    if blackboard.get(fact) != Some(value) {
+        blackboard.insert(fact, value);
+    }
+}
+
+/// The facts that are true, every other fact is false
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct WorldState(BTreeSet<&'static str>);
+
+impl WorldState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, fact: Fact, value: bool) -> Self {
+        self.set(fact.name(), value);
+        self
+    }
+
+    pub fn is(&self, fact: Fact) -> bool {
+        self.0.contains(fact.name())
+    }
+
+    fn set(&mut self, fact: &'static str, value: bool) {
+        if value {
+            self.0.insert(fact);
+        } else {
+            self.0.remove(fact);
+        }
+    }
+
+    fn satisfies(&self, conditions: &[(&'static str, bool)]) -> bool {
+        conditions.iter().all(|&(fact, value)| self.0.contains(fact) == value)
+    }
+}
+
+/// The facts an agent wants to be true, or false
+#[derive(Clone, Debug)]
+pub struct Goal {
+    name: &'static str,
+    conditions: Vec<(&'static str, bool)>,
+}
+
+impl Goal {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            conditions: Vec::new(),
+        }
+    }
+
+    pub fn with(mut self, fact: Fact, value: bool) -> Self {
+        self.conditions.push((fact.name(), value));
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// A big-brain action the planner can chain, described by the facts it needs and the facts it changes
+#[derive(Clone, Debug)]
+pub struct GoapAction {
+    name: &'static str,
+    cost: u32,
+    preconditions: Vec<(&'static str, bool)>,
+    effects: Vec<(&'static str, bool)>,
+    action: Arc<dyn ActionBuilder>,
+}
+
+impl GoapAction {
+    pub fn new(name: &'static str, cost: u32, action: impl ActionBuilder + 'static) -> Self {
+        Self {
+            name,
+            cost,
+            preconditions: Vec::new(),
+            effects: Vec::new(),
+            action: Arc::new(action),
+        }
+    }
+
+    /// Only plans the action once the fact has the value.
+    pub fn requires(mut self, fact: Fact, value: bool) -> Self {
+        self.preconditions.push((fact.name(), value));
+        self
+    }
+
+    /// Expects the fact to have the value once the action succeeds.
+    pub fn causes(mut self, fact: Fact, value: bool) -> Self {
+        self.effects.push((fact.name(), value));
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn cost(&self) -> u32 {
+        self.cost
+    }
+
+    fn apply(&self, state: &WorldState) -> WorldState {
+        let mut state = state.clone();
+        for &(fact, value) in &self.effects {
+            state.set(fact, value);
+        }
+        state
+    }
+
+    fn facts(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.preconditions.iter().chain(&self.effects).map(|&(fact, _)| fact)
+    }
+}
+
+/// Finds the cheapest chain of actions that takes `start` to a state that satisfies the goal.
+///
+/// An empty plan means the goal is already satisfied, `None` means it cannot be reached.
+///
+/// # Examples
+///
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_game::blackboard::BlackboardKey;
+/// use bevy_game::planner::{plan, GoapAction, Goal, WorldState};
+/// use big_brain::prelude::*;
+///
+/// #[derive(Clone, Component, Debug, ActionBuilder)]
+/// struct Idle;
+///
+/// const HAS_AXE: BlackboardKey<bool> = BlackboardKey::new("has_axe");
+/// const HAS_WOOD: BlackboardKey<bool> = BlackboardKey::new("has_wood");
+///
+/// let actions = [
+///     GoapAction::new("FetchAxe", 2, Idle).causes(HAS_AXE, true),
+///     GoapAction::new("Chop", 1, Idle).requires(HAS_AXE, true).causes(HAS_WOOD, true),
+///     GoapAction::new("Scavenge", 5, Idle).causes(HAS_WOOD, true),
+/// ];
+/// let goal = Goal::new("HaveWood").with(HAS_WOOD, true);
+///
+/// let names = |plan: Vec<&GoapAction>| plan.iter().map(|action| action.name()).collect::<Vec<_>>();
+/// assert_eq!(names(plan(&WorldState::new(), &goal, &actions).unwrap()), ["FetchAxe", "Chop"]);
+/// assert_eq!(names(plan(&WorldState::new(), &goal, &actions[1..]).unwrap()), ["Scavenge"]);
+/// assert!(plan(&WorldState::new().with(HAS_WOOD, true), &goal, &actions).unwrap().is_empty());
+/// assert!(plan(&WorldState::new(), &goal, &actions[..1]).is_none());
+/// ```
+pub fn plan<'a>(
+    start: &WorldState,
+    goal: &Goal,
+    actions: impl IntoIterator<Item = &'a GoapAction>,
+) -> Option<Vec<&'a GoapAction>> {
+    let actions: Vec<_> = actions.into_iter().collect();
+
+    // Remembering the action that led to each state lets the plan be read back off the path
+    let (path, _) = dijkstra(
+        &(start.clone(), None),
+        |(state, _): &(WorldState, Option<usize>)| {
+            actions
+                .iter()
+                .enumerate()
+                .filter(|(_, action)| state.satisfies(&action.preconditions))
+                .map(|(index, action)| ((action.apply(state), Some(index)), action.cost))
+                .collect::<Vec<_>>()
+        },
+        |(state, _)| state.satisfies(&goal.conditions),
+    )?;
+
+    Some(
+        path.into_iter()
+            .filter_map(|(_, index)| Some(actions[index?]))
+            .collect(),
+    )
+}
+
+/// Every action `PursueGoal` can plan with
+#[derive(Default, Resource)]
+pub struct GoapActions(Vec<GoapAction>);
+
+impl GoapActions {
+    pub fn register(&mut self, action: GoapAction) {
+        self.0.push(action);
+    }
+
+    /// Reads every fact the actions or the goal depend on from the agent's blackboards.
+    fn state(&self, goal: &Goal, agent: Entity, blackboards: &Blackboards) -> WorldState {
+        let facts = self
+            .0
+            .iter()
+            .flat_map(GoapAction::facts)
+            .chain(goal.conditions.iter().map(|&(fact, _)| fact));
+
+        let mut state = WorldState::new();
+        for fact in facts {
+            state.set(fact, blackboards.get(agent, Fact::new(fact)).unwrap_or(false));
+        }
+        state
+    }
+}
+
+#[ext(name = PlannerAppExt)]
+pub impl App {
+    /// Makes an action available to every `PursueGoal`.
+    fn register_goap_action(&mut self, action: GoapAction) -> &mut App {
+        self.world_mut()
+            .get_resource_or_insert_with(GoapActions::default)
+            .register(action);
+        self
+    }
+}
+
+/// Plans how to reach a goal from the facts on the actor's blackboards and runs the plan one action at a time.
+///
+/// When an action fails the rest of the plan is thrown away and a new one is made without that action, the goal is
+/// only given up on once no plan is left.
+#[derive(Clone, Component, Debug, ActionBuilder)]
+pub struct PursueGoal {
+    goal: Goal,
+    plan: VecDeque<GoapAction>,
+    /// The step being run, and the name of the action it was spawned from
+    active: Option<(Entity, &'static str)>,
+    failed: Vec<&'static str>,
+}
+
+impl PursueGoal {
+    pub fn new(goal: Goal) -> Self {
+        Self {
+            goal,
+            plan: VecDeque::new(),
+            active: None,
+            failed: Vec::new(),
+        }
+    }
+
+    /// Plans from the current facts and starts the first action, returning whether there was anything to do.
+    fn replan(
+        &mut self,
+        commands: &mut Commands,
+        this: Entity,
+        actor: Entity,
+        actions: &GoapActions,
+        blackboards: &Blackboards,
+    ) -> Option<bool> {
+        let state = actions.state(&self.goal, actor, blackboards);
+        let usable = actions.0.iter().filter(|action| !self.failed.contains(&action.name));
+        let plan = plan(&state, &self.goal, usable)?;
+
+        debug!(
+            "Planned {:?} for {}",
+            plan.iter().map(|action| action.name).collect::<Vec<_>>(),
+            self.goal.name
+        );
+        self.plan = plan.into_iter().cloned().collect();
+        Some(self.advance(commands, this, actor))
+    }
+
+    /// Starts the next action of the plan, returning whether there was one.
+    fn advance(&mut self, commands: &mut Commands, this: Entity, actor: Entity) -> bool {
+        if let Some((active, _)) = self.active.take() {
+            commands.entity(active).despawn_recursive();
+        }
+
+        let Some(next) = self.plan.pop_front() else {
+            return false;
+        };
+
+        let step = spawn_action(next.action.as_ref(), commands, actor);
+        commands.entity(this).push_children(&[step]);
+        self.active = Some((step, next.name));
+        true
+    }
+}
+
+pub fn pursue_goal_system(
+    mut commands: Commands,
+    actions: Res<GoapActions>,
+    blackboards: Blackboards,
+    mut pursuits: Query<(Entity, &Actor, &mut PursueGoal, &ActionSpan)>,
+    mut states: Query<&mut ActionState>,
+) {
+    for (this, Actor(actor), mut pursuit, span) in &mut pursuits {
+        let _guard = span.span().enter();
+
+        let Ok(state) = states.get(this).cloned() else {
+            continue;
+        };
+
+        // A step spawned this frame does not exist until the commands are applied
+        let step_state = pursuit.active.and_then(|(step, _)| states.get(step).ok().cloned());
+
+        let outcome = match (state, step_state) {
+            (ActionState::Requested, _) => {
+                pursuit.failed.clear();
+                match pursuit.replan(&mut commands, this, *actor, &actions, &blackboards) {
+                    Some(true) => ActionState::Executing,
+                    Some(false) => ActionState::Success,
+                    None => ActionState::Failure,
+                }
+            }
+            (ActionState::Executing, Some(ActionState::Init)) => {
+                *states.get_mut(pursuit.active.unwrap().0).unwrap() = ActionState::Requested;
+                continue;
+            }
+            (ActionState::Executing, Some(ActionState::Success)) => {
+                if pursuit.advance(&mut commands, this, *actor) {
+                    continue;
+                }
+                ActionState::Success
+            }
+            (ActionState::Executing, Some(ActionState::Failure)) => {
+                if let Some((_, failed)) = pursuit.active {
+                    debug!("{} failed while pursuing {}, replanning", failed, pursuit.goal.name);
+                    pursuit.failed.push(failed);
+                }
+                match pursuit.replan(&mut commands, this, *actor, &actions, &blackboards) {
+                    Some(true) => continue,
+                    // The goal was reached even though the last step failed
+                    Some(false) => ActionState::Success,
+                    None => ActionState::Failure,
+                }
+            }
+            (ActionState::Cancelled, Some(ActionState::Init | ActionState::Requested | ActionState::Executing)) => {
+                *states.get_mut(pursuit.active.unwrap().0).unwrap() = ActionState::Cancelled;
+                continue;
+            }
+            (ActionState::Cancelled, Some(ActionState::Cancelled)) => continue,
+            (ActionState::Cancelled, _) => {
+                pursuit.plan.clear();
+                if let Some((active, _)) = pursuit.active.take() {
+                    commands.entity(active).despawn_recursive();
+                }
+                ActionState::Failure
+            }
+            _ => continue,
+        };
+
+        *states.get_mut(this).unwrap() = outcome;
+    }
+}
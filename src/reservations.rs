@@ -1,24 +1,61 @@
 use crate::assets::UiAssets;
+use crate::ext::TilePosExt;
 use crate::states::States::Play;
+use crate::stockpile::StockpileCell;
 use crate::worldgen::{TILEMAP_SIZE, TILEMAP_TILE_SIZE, TILEMAP_TYPE};
+use crate::SimulationSet;
+use bevy::ecs::entity::Entities;
 use bevy::prelude::*;
+use bevy::utils::FixedState;
 use bevy_ecs_tilemap::map::{TilemapId, TilemapTexture};
 use bevy_ecs_tilemap::prelude::{TileBundle, TilePos, TileStorage, TileTextureIndex};
 use bevy_ecs_tilemap::TilemapBundle;
-use bevy_spatial::*;
+use bevy_spatial::kdtree::KDTree2;
+use bevy_spatial::point::Point2;
+use big_brain::prelude::*;
 use derive_builder::Builder;
+use kd_tree::KdTree;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 pub struct ReservationsPlugin;
 
 impl Plugin for ReservationsPlugin {
     fn build(&self, app: &mut App) {
         info!("ReservationsPlugin#build");
-        app.add_event::<ReservationRequest>()
+        app.init_resource::<ReservationLedger>()
+            .init_resource::<ReservableTree>()
+            .add_event::<ReservationRequest>()
+            .add_event::<PreemptReservation>()
+            .add_event::<ReleaseReservation>()
+            .add_event::<ReservationEvent>()
             .add_event::<RemoveReservation>()
-            .add_systems(Update, reservation_system)
-            // This will create a `KDTree2<Reservable>` resource which can be used for querying
-            .add_plugins(AutomaticUpdate::<Reservable>::new().with_spatial_ds(SpatialStructure::KDTree2));
+            .add_systems(
+                FixedUpdate,
+                (
+                    release_on_failure_system.run_if(in_state(Play)),
+                    // Releases go first so that a failed action never takes back what was reserved after it failed
+                    release_reservation_system,
+                    reservation_system,
+                    preempt_reservation_system,
+                    expire_reservations_system,
+                    sync_reserved_system,
+                )
+                    .chain()
+                    .in_set(SimulationSet::Reserve),
+            )
+            .add_systems(
+                FixedUpdate,
+                update_reservable_tree_system.in_set(SimulationSet::Prepare),
+            );
+    }
+}
+
+/// Visualizes reservable tiles with an overlay tilemap of xs
+pub struct ReservationsRenderPlugin;
 
+impl Plugin for ReservationsRenderPlugin {
+    fn build(&self, app: &mut App) {
         app.add_systems(Update, on_reserved_removed.run_if(in_state(Play)));
 
         app.add_systems(Update, on_reservable_added.run_if(in_state(Play)));
@@ -27,23 +64,308 @@ impl Plugin for ReservationsPlugin {
     }
 }
 
+/// Something that can be reserved, either an entity such as a bush or a stockpile cell, or a bare tile such as a
+/// standing spot or a construction site
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum ReservationTarget {
+    Entity(Entity),
+    Tile(TilePos),
+}
+
+impl ReservationTarget {
+    pub fn entity(self) -> Option<Entity> {
+        match self {
+            ReservationTarget::Entity(entity) => Some(entity),
+            ReservationTarget::Tile(_) => None,
+        }
+    }
+}
+
+impl From<Entity> for ReservationTarget {
+    fn from(entity: Entity) -> Self {
+        ReservationTarget::Entity(entity)
+    }
+}
+
+impl From<TilePos> for ReservationTarget {
+    fn from(tilepos: TilePos) -> Self {
+        ReservationTarget::Tile(tilepos)
+    }
+}
+
+/// Asks for `target` to be reserved for `requester`, which fails if it is already reserved to capacity
 #[derive(Builder, Event)]
 pub struct ReservationRequest {
     requester: Entity,
-    target: Entity,
+    #[builder(setter(into))]
+    target: ReservationTarget,
+    /// How long, in seconds, the reservation lasts before it is released on its own
+    #[builder(default)]
+    ttl: Option<f32>,
+    /// The action asking for the reservation, which gives it up if it fails
+    #[builder(default, setter(strip_option))]
+    action: Option<Entity>,
+}
+
+/// Reserves `target` for `requester`, taking it away from whoever reserved it first if it is reserved to capacity,
+/// for work that cannot wait.
+///
+/// Unlike a `ReservationRequest` the target does not have to be designated.
+#[derive(Builder, Event)]
+pub struct PreemptReservation {
+    requester: Entity,
+    #[builder(setter(into))]
+    target: ReservationTarget,
+    /// How long, in seconds, the reservation lasts before it is released on its own
+    #[builder(default)]
+    ttl: Option<f32>,
+    /// The action asking for the reservation, which gives it up if it fails
+    #[builder(default, setter(strip_option))]
+    action: Option<Entity>,
+}
+
+/// Asks for what `owner` has reserved to be released, either one target or, without one, everything
+#[derive(Event)]
+pub struct ReleaseReservation {
+    pub owner: Entity,
+    pub target: Option<ReservationTarget>,
+    pub reason: ReleaseReason,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReleaseReason {
+    /// The work on the target is finished
+    Done,
+    /// The action holding the reservation failed or was cancelled
+    Failed,
+    /// The designation on the target was withdrawn, so it is not made reservable again
+    Cancelled,
+    /// The reservation outlived its time to live
+    Expired,
+    /// Someone else took the target over with a `PreemptReservation`
+    Preempted,
+    OwnerDespawned,
+    TargetDespawned,
+}
+
+/// Published whenever a reservation is made or released
+#[derive(Clone, Copy, Debug, Event, PartialEq)]
+pub enum ReservationEvent {
+    Reserved {
+        owner: Entity,
+        target: ReservationTarget,
+    },
+    Released {
+        owner: Entity,
+        target: ReservationTarget,
+        reason: ReleaseReason,
+    },
 }
 
 #[derive(Component, Default)]
 pub struct Reservable;
 
-#[derive(Builder, Component)]
-pub struct Reservation {
-    pub target: Entity,
-}
+/// Every `Reservable` entity, by position, used to find the nearest targets without looking at all of them
+pub type ReservableTree = KDTree2<Reservable>;
+
+/// How many agents can reserve an entity at once, entities without a capacity and tiles take a single agent
+#[derive(Clone, Copy, Component, Debug)]
+pub struct Capacity(pub usize);
 
+/// Marks an entity that is reserved to capacity, so that it can be filtered out of queries
 #[derive(Component)]
 pub struct Reserved;
 
+/// A claim an agent holds on a target
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reservation {
+    pub owner: Entity,
+    pub target: ReservationTarget,
+    /// When the reservation was made, measured by `Time::elapsed_seconds`
+    pub created: f32,
+    /// How long, in seconds, the reservation lasts before it is released on its own
+    pub ttl: Option<f32>,
+    /// The action that made the reservation followed by the steps and plans it is nested in, empty until an action
+    /// takes up a reservation that was restored from a save
+    pub actions: Vec<Entity>,
+}
+
+impl Reservation {
+    pub fn expired(&self, now: f32) -> bool {
+        self.ttl.is_some_and(|ttl| now - self.created > ttl)
+    }
+}
+
+/// Every reservation, by target and by owner.
+///
+/// Only the reservation systems should change the ledger, everything else asks it who reserved what.
+///
+/// # Examples
+///
+/// ```
+/// use bevy::prelude::Entity;
+/// use bevy_ecs_tilemap::prelude::TilePos;
+/// use bevy_game::reservations::{Reservation, ReservationLedger, ReservationTarget};
+///
+/// let mut ledger = ReservationLedger::default();
+/// let workbench = ReservationTarget::Entity(Entity::from_raw(0));
+/// let spot = ReservationTarget::Tile(TilePos { x: 4, y: 2 });
+/// let reservation = |owner, target| Reservation {
+///     owner: Entity::from_raw(owner),
+///     target,
+///     created: 0.0,
+///     ttl: None,
+///     actions: vec![],
+/// };
+///
+/// // The workbench seats two and the standing spot one
+/// assert!(ledger.reserve(reservation(1, workbench), 2));
+/// assert!(ledger.reserve(reservation(2, workbench), 2));
+/// assert!(!ledger.reserve(reservation(3, workbench), 2));
+/// assert!(ledger.reserve(reservation(1, spot), 1));
+/// assert!(!ledger.reserve(reservation(2, spot), 1));
+///
+/// assert_eq!(ledger.who_reserved(workbench).count(), 2);
+/// assert_eq!(ledger.reservations_of(Entity::from_raw(1)).count(), 2);
+///
+/// assert!(ledger.release(Entity::from_raw(1), workbench).is_some());
+/// assert!(!ledger.is_reserved_by(workbench, Entity::from_raw(1)));
+/// assert_eq!(ledger.reservations_of(Entity::from_raw(1)).count(), 1);
+/// ```
+#[derive(Debug, Default, Resource)]
+pub struct ReservationLedger {
+    /// Hashed the same way every run, since reservations expire in the order they are kept in
+    by_target: HashMap<ReservationTarget, Vec<Reservation>, FixedState>,
+    by_owner: HashMap<Entity, Vec<ReservationTarget>>,
+    /// Entities whose reservations changed since the `Reserved` markers were last brought up to date
+    dirty: HashSet<Entity, FixedState>,
+    /// Entities that were `Reservable` when they were reserved to capacity, and are made so again once there is room
+    restore: HashSet<Entity>,
+}
+
+impl ReservationLedger {
+    /// Returns the agents holding a reservation on `target`.
+    pub fn who_reserved(&self, target: impl Into<ReservationTarget>) -> impl Iterator<Item = Entity> + '_ {
+        self.by_target
+            .get(&target.into())
+            .into_iter()
+            .flatten()
+            .map(|reservation| reservation.owner)
+    }
+
+    /// Returns every reservation an agent holds.
+    pub fn reservations_of(&self, agent: Entity) -> impl Iterator<Item = &Reservation> + '_ {
+        self.by_owner
+            .get(&agent)
+            .into_iter()
+            .flatten()
+            .filter_map(move |target| {
+                self.by_target
+                    .get(target)?
+                    .iter()
+                    .find(|reservation| reservation.owner == agent)
+            })
+    }
+
+    /// Returns how many agents hold a reservation on `target`.
+    pub fn count(&self, target: impl Into<ReservationTarget>) -> usize {
+        self.by_target.get(&target.into()).map_or(0, Vec::len)
+    }
+
+    pub fn is_reserved(&self, target: impl Into<ReservationTarget>) -> bool {
+        self.count(target) > 0
+    }
+
+    pub fn is_reserved_by(&self, target: impl Into<ReservationTarget>, agent: Entity) -> bool {
+        self.who_reserved(target).any(|owner| owner == agent)
+    }
+
+    /// Returns whether `agent` holds `target` for one of its actions, rather than because it was restored from a save
+    /// and no action has asked for it since.
+    pub fn is_claimed_by(&self, target: impl Into<ReservationTarget>, agent: Entity) -> bool {
+        self.by_target
+            .get(&target.into())
+            .into_iter()
+            .flatten()
+            .any(|reservation| reservation.owner == agent && !reservation.actions.is_empty())
+    }
+
+    /// Returns every reservation.
+    pub fn iter(&self) -> impl Iterator<Item = &Reservation> + '_ {
+        self.by_target.values().flatten()
+    }
+
+    /// Records a reservation, unless its owner already holds the target or the target is reserved to `capacity`.
+    ///
+    /// Returns true if the reservation was recorded.
+    pub fn reserve(&mut self, reservation: Reservation, capacity: usize) -> bool {
+        let (owner, target) = (reservation.owner, reservation.target);
+        if self.count(target) >= capacity || self.is_reserved_by(target, owner) {
+            return false;
+        }
+
+        self.by_target.entry(target).or_default().push(reservation);
+        self.by_owner.entry(owner).or_default().push(target);
+        self.dirty.extend(target.entity());
+        true
+    }
+
+    /// Removes the reservation `owner` holds on `target`, returning it if there was one.
+    pub fn release(&mut self, owner: Entity, target: ReservationTarget) -> Option<Reservation> {
+        let reservations = self.by_target.get_mut(&target)?;
+        let index = reservations.iter().position(|reservation| reservation.owner == owner)?;
+        let reservation = reservations.remove(index);
+
+        if reservations.is_empty() {
+            self.by_target.remove(&target);
+        }
+
+        if let Some(targets) = self.by_owner.get_mut(&owner) {
+            targets.retain(|&held| held != target);
+            if targets.is_empty() {
+                self.by_owner.remove(&owner);
+            }
+        }
+
+        self.dirty.extend(target.entity());
+        Some(reservation)
+    }
+
+    /// Hands the reservation `owner` holds on `target` to `actions`, unless an action holds it already.
+    fn claim(&mut self, owner: Entity, target: ReservationTarget, actions: Vec<Entity>) {
+        let held = self
+            .by_target
+            .get_mut(&target)
+            .into_iter()
+            .flatten()
+            .find(|reservation| reservation.owner == owner);
+
+        if let Some(reservation) = held.filter(|reservation| reservation.actions.is_empty()) {
+            reservation.actions = actions;
+        }
+    }
+}
+
+/// Rebuilds the reservable tree at the start of a step whenever something became reservable or stopped being so, so
+/// that every agent looks at the same targets within a step
+fn update_reservable_tree_system(
+    mut tree: ResMut<ReservableTree>,
+    added: Query<(), Added<Reservable>>,
+    mut removed: RemovedComponents<Reservable>,
+    reservables: Query<(Entity, &TilePos), With<Reservable>>,
+) {
+    let changed = !added.is_empty() || removed.read().count() > 0;
+    if !changed {
+        return;
+    }
+
+    let points = reservables
+        .iter()
+        .map(|(target, tilepos)| Point2::from((target, tilepos.to_world_space())))
+        .collect();
+    tree.tree = KdTree::build_by_ordered_float(points);
+}
+
 /// Tag component for the reservation tilemap used to visualize reservations
 #[derive(Component)]
 pub struct ReservationTilemap;
@@ -54,28 +376,256 @@ pub struct RemoveReservation {
     pub tilepos: TilePos,
 }
 
+/// Returns how many agents can reserve `target` at once.
+pub(crate) fn capacity_of(target: ReservationTarget, capacities: &Query<&Capacity>) -> usize {
+    target
+        .entity()
+        .and_then(|entity| capacities.get(entity).ok())
+        .map_or(1, |capacity| capacity.0)
+}
+
+/// Returns `action` followed by the steps and plans it is nested in, up to the one its thinker picked.
+fn nested_actions(action: Option<Entity>, parents: &Query<Option<&Parent>, With<ActionState>>) -> Vec<Entity> {
+    // Actions are children of the action they are nested in, while the one the thinker picked has no parent
+    std::iter::successors(action, |&action| {
+        let parent = parents.get(action).ok()??.get();
+        parents.contains(parent).then_some(parent)
+    })
+    .collect()
+}
+
 fn reservation_system(
-    mut commands: Commands,
+    time: Res<Time>,
+    mut ledger: ResMut<ReservationLedger>,
     mut reservation_requests: EventReader<ReservationRequest>,
-    query: Query<Entity, (With<Reservable>, Without<Reserved>)>,
+    reservable: Query<(), Or<(With<Reservable>, With<StockpileCell>, With<Capacity>)>>,
+    capacities: Query<&Capacity>,
+    parents: Query<Option<&Parent>, With<ActionState>>,
+    mut reservation_writer: EventWriter<ReservationEvent>,
 ) {
     for reservation_request in reservation_requests.read() {
-        if let Ok(target) = query.get(reservation_request.target) {
-            commands
-                .entity(reservation_request.requester)
-                .insert(ReservationBuilder::default().target(target).build().unwrap());
+        let (requester, target) = (reservation_request.requester, reservation_request.target);
+        if ledger.is_reserved_by(target, requester) {
+            // Asked for again by the action that picks up a reservation restored from a save
+            ledger.claim(requester, target, nested_actions(reservation_request.action, &parents));
+            continue;
+        }
 
-            commands.entity(target).insert(Reserved);
+        // Entities have to be designated, or be made to be reserved, while any tile can be reserved
+        let designated = target.entity().is_none_or(|entity| reservable.contains(entity));
+        let reservation = Reservation {
+            owner: requester,
+            target,
+            created: time.elapsed_seconds(),
+            ttl: reservation_request.ttl,
+            actions: nested_actions(reservation_request.action, &parents),
+        };
 
-            // This will remove the entity from the `KDTree2<Reservable>` resource
-            commands.entity(target).remove::<Reservable>();
+        if designated && ledger.reserve(reservation, capacity_of(target, &capacities)) {
+            reservation_writer.send(ReservationEvent::Reserved {
+                owner: requester,
+                target,
+            });
 
-            trace!("{:?} has reserved {:?}", reservation_request.requester, target);
+            trace!("{:?} has reserved {:?}", requester, target);
         } else {
-            error!(
-                "{:?} failed to reserve {:?}",
-                reservation_request.requester, reservation_request.target
-            );
+            error!("{:?} failed to reserve {:?}", requester, target);
+        }
+    }
+}
+
+fn preempt_reservation_system(
+    time: Res<Time>,
+    mut ledger: ResMut<ReservationLedger>,
+    mut preempt_requests: EventReader<PreemptReservation>,
+    capacities: Query<&Capacity>,
+    parents: Query<Option<&Parent>, With<ActionState>>,
+    mut reservation_writer: EventWriter<ReservationEvent>,
+) {
+    for request in preempt_requests.read() {
+        let (requester, target) = (request.requester, request.target);
+        if ledger.is_reserved_by(target, requester) {
+            ledger.claim(requester, target, nested_actions(request.action, &parents));
+            continue;
+        }
+
+        // The longest held reservations make way first
+        let capacity = capacity_of(target, &capacities);
+        while ledger.count(target) >= capacity {
+            let Some(owner) = ledger.who_reserved(target).next() else {
+                break;
+            };
+
+            ledger.release(owner, target);
+            reservation_writer.send(ReservationEvent::Released {
+                owner,
+                target,
+                reason: ReleaseReason::Preempted,
+            });
+        }
+
+        let reservation = Reservation {
+            owner: requester,
+            target,
+            created: time.elapsed_seconds(),
+            ttl: request.ttl,
+            actions: nested_actions(request.action, &parents),
+        };
+
+        if ledger.reserve(reservation, capacity) {
+            reservation_writer.send(ReservationEvent::Reserved {
+                owner: requester,
+                target,
+            });
+        }
+    }
+}
+
+fn release_reservation_system(
+    mut commands: Commands,
+    mut ledger: ResMut<ReservationLedger>,
+    mut release_requests: EventReader<ReleaseReservation>,
+    mut reservation_writer: EventWriter<ReservationEvent>,
+) {
+    for &ReleaseReservation { owner, target, reason } in release_requests.read() {
+        let targets: Vec<_> = match target {
+            Some(target) => vec![target],
+            None => ledger
+                .reservations_of(owner)
+                .map(|reservation| reservation.target)
+                .collect(),
+        };
+
+        for target in targets {
+            // The same reservation can be released for several reasons in one frame, such as a step and its
+            // sequence both failing, so only the first release counts
+            if ledger.release(owner, target).is_none() {
+                continue;
+            }
+
+            if let (ReleaseReason::Cancelled, Some(entity)) = (reason, target.entity()) {
+                // The target must not go back to being reservable once nobody holds it
+                ledger.restore.remove(&entity);
+                if let Some(mut entity) = commands.get_entity(entity) {
+                    entity.remove::<Reservable>();
+                }
+            }
+
+            reservation_writer.send(ReservationEvent::Released { owner, target, reason });
+        }
+    }
+}
+
+/// Releases reservations that ran out of time or whose owner or target is gone
+fn expire_reservations_system(
+    time: Res<Time>,
+    entities: &Entities,
+    mut ledger: ResMut<ReservationLedger>,
+    mut reservation_writer: EventWriter<ReservationEvent>,
+) {
+    let now = time.elapsed_seconds();
+
+    let expired: Vec<_> = ledger
+        .iter()
+        .filter_map(|reservation| {
+            let reason = if !entities.contains(reservation.owner) {
+                ReleaseReason::OwnerDespawned
+            } else if reservation
+                .target
+                .entity()
+                .is_some_and(|target| !entities.contains(target))
+            {
+                ReleaseReason::TargetDespawned
+            } else if reservation.expired(now) {
+                ReleaseReason::Expired
+            } else {
+                return None;
+            };
+
+            Some((reservation.owner, reservation.target, reason))
+        })
+        .collect();
+
+    for (owner, target, reason) in expired {
+        ledger.release(owner, target);
+        reservation_writer.send(ReservationEvent::Released { owner, target, reason });
+    }
+}
+
+/// Marks entities that are reserved to capacity as `Reserved`, taking them off the `Reservable` targets until
+/// there is room on them again
+fn sync_reserved_system(
+    mut commands: Commands,
+    entities: &Entities,
+    mut ledger: ResMut<ReservationLedger>,
+    targets: Query<(Has<Reservable>, Has<Reserved>, Option<&Capacity>)>,
+) {
+    let dirty: Vec<_> = ledger.dirty.drain().collect();
+
+    for entity in dirty {
+        let Ok((reservable, reserved, capacity)) = targets.get(entity) else {
+            if entities.contains(entity) {
+                // Reserved as it was being spawned, such as when a colony is loaded, so try again next frame
+                ledger.dirty.insert(entity);
+            } else {
+                ledger.restore.remove(&entity);
+            }
+            continue;
+        };
+
+        let full = ledger.count(entity) >= capacity.map_or(1, |capacity| capacity.0);
+
+        if full && !reserved {
+            commands.entity(entity).insert(Reserved);
+
+            // This will stop it from being picked as a job until there is room on it again
+            if reservable {
+                commands.entity(entity).remove::<Reservable>();
+                ledger.restore.insert(entity);
+            }
+        } else if !full && reserved {
+            commands.entity(entity).remove::<Reserved>();
+
+            if ledger.restore.remove(&entity) {
+                commands.entity(entity).insert(Reservable);
+            }
+        }
+    }
+}
+
+/// Releases the reservations an action made, or that were made by the steps and plans nested in it, as soon as it
+/// fails, which includes being cancelled.
+///
+/// Reservations restored from a save that no action has asked for by the time the action the thinker picked first
+/// is over are not needed anymore, and are released too.
+fn release_on_failure_system(
+    actions: Query<(Entity, &Actor, &ActionState, Option<&Parent>), (Changed<ActionState>, Without<Thinker>)>,
+    nested: Query<(), With<ActionState>>,
+    ledger: Res<ReservationLedger>,
+    mut release_writer: EventWriter<ReleaseReservation>,
+) {
+    for (action, Actor(actor), action_state, parent) in actions.iter() {
+        let failed = *action_state == ActionState::Failure;
+        // Thinkers do not parent the actions they pick, only steps and plans do
+        let picked_by_thinker = parent.is_none_or(|parent| !nested.contains(parent.get()));
+        let over = failed || (picked_by_thinker && *action_state == ActionState::Success);
+        if !over {
+            continue;
+        }
+
+        for reservation in ledger.reservations_of(*actor) {
+            let unclaimed = reservation.actions.is_empty() && picked_by_thinker;
+            if unclaimed || (failed && reservation.actions.contains(&action)) {
+                release_writer.send(ReleaseReservation {
+                    owner: *actor,
+                    target: Some(reservation.target),
+                    reason: if failed {
+                        ReleaseReason::Failed
+                    } else {
+                        ReleaseReason::Done
+                    },
+                });
+            }
         }
     }
 }
@@ -0,0 +1,616 @@
+use crate::agent::{Bush, Rock};
+use crate::assets::CharacterAssets;
+use crate::behavior::Profession;
+use crate::blackboard::{Blackboard, ColonyBlackboard};
+use crate::items::{Inventory, Item, ItemStack};
+use crate::jobs::{Job, JobBoard, JobKind, Skills, WorkPriorities};
+use crate::needs::{spawn_bed, Bed, Needs};
+use crate::orders::Order;
+use crate::reservations::{
+    capacity_of, Capacity, Reservable, Reservation, ReservationLedger, ReservationTarget, ReservationTilemap, Reserved,
+};
+use crate::states::States::{Menu, Play};
+use crate::stockpile::{spawn_loose_item, spawn_stockpile, LooseItem, Stockpile, StockpileCell};
+use crate::villager::{spawn_villager, Movement};
+use crate::worldgen::{bush_bundle, rock_bundle, World, WorldSeed, TILEMAP_SIZE, TILEMAP_TILE_SIZE, TILEMAP_TYPE};
+use crate::SimulationSet;
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use big_brain::prelude::HasThinker;
+use grid_2d::{Grid, Size};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// Bump this when the layout of `SaveFile` changes after a release, so that saves from it are rejected instead of
+/// misread
+pub const SAVE_VERSION: u32 = 1;
+
+/// Where the colony is saved to and loaded from, relative to the working directory
+pub const SAVE_PATH: &str = "colony.json";
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveColony>()
+            .add_event::<LoadColony>()
+            .add_systems(Update, save_hotkey_system.run_if(in_state(Play)))
+            // Saved in step with the simulation, so that villagers are not saved where they were last drawn
+            .add_systems(
+                FixedUpdate,
+                save_system.after(SimulationSet::Move).run_if(in_state(Play)),
+            )
+            .add_systems(Update, load_system.run_if(in_state(Menu)));
+    }
+}
+
+/// Event to request that the running colony is written to `SAVE_PATH`
+#[derive(Event)]
+pub struct SaveColony;
+
+/// Event to request that the colony in `SAVE_PATH` is restored, skipping worldgen
+#[derive(Event)]
+pub struct LoadColony;
+
+/// A snapshot of a colony that can be written to disk and resumed later.
+///
+/// Big-brain thinkers are not part of the snapshot, they are rebuilt when the villager is restored and pick their
+/// action again from what was saved. Because the villager's reservations, `Blackboard` and `Order` are kept, a
+/// villager that was on its way to a bush, bed or stockpile cell resumes by walking back to the one it reserved, and
+/// one that was carrying out an order carries on with it. Progress within an action, such as how long a bush was
+/// being gathered, is lost.
+#[derive(Deserialize, Serialize)]
+pub struct SaveFile {
+    pub version: u32,
+    pub seed: u64,
+    /// The `World` tile values in row-major order
+    pub terrain: Vec<u16>,
+    pub tilemaps: Vec<SavedTilemap>,
+    pub villagers: Vec<SavedVillager>,
+    pub stockpiles: Vec<SavedStockpile>,
+    pub loose_items: Vec<SavedItems>,
+    pub beds: Vec<SavedBed>,
+    /// The facts on the `ColonyBlackboard`
+    pub colony: Blackboard,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SavedTilemap {
+    pub name: String,
+    pub texture: String,
+    pub z: f32,
+    pub tiles: Vec<SavedTile>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SavedTile {
+    /// The entity the tile had when it was saved, which blackboards may refer to
+    pub entity: Entity,
+    pub position: TilePos,
+    pub texture_index: u32,
+    pub animation: Option<AnimatedTile>,
+    pub bush: bool,
+    pub rock: bool,
+    pub reservable: bool,
+    pub reserved: bool,
+    /// The job posted for the tile, if it was designated
+    pub job: Option<SavedJob>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SavedJob {
+    pub kind: JobKind,
+    pub priority: u8,
+    pub required_skill: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SavedVillager {
+    /// The entity the villager had when it was saved, which blackboards may refer to
+    pub entity: Entity,
+    pub position: [f32; 2],
+    pub path: Vec<TilePos>,
+    pub blackboard: Blackboard,
+    pub needs: Needs,
+    pub inventory: Inventory,
+    pub work_priorities: WorkPriorities,
+    pub skills: Skills,
+    pub profession: Profession,
+    /// Everything the villager had reserved
+    pub reservations: Vec<SavedReservation>,
+    /// The order the villager was carrying out
+    pub order: Option<SavedOrder>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SavedReservation {
+    /// The target, pointing at the entity it had when it was saved if it is not a bare tile
+    pub target: ReservationTarget,
+    pub capacity: usize,
+    pub ttl: Option<f32>,
+    /// How long, in seconds, was left before the reservation expired
+    pub remaining: Option<f32>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub enum SavedOrder {
+    MoveTo(TilePos),
+    /// Gather the bush at this position
+    Gather(TilePos),
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SavedStockpile {
+    pub min: TilePos,
+    pub max: TilePos,
+    pub allowed: Vec<Item>,
+    pub cells: Vec<SavedCell>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SavedCell {
+    /// The entity the cell had when it was saved, which reservations may refer to
+    pub entity: Entity,
+    pub position: TilePos,
+    pub contents: Option<ItemStack>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SavedBed {
+    /// The entity the bed had when it was saved, which reservations may refer to
+    pub entity: Entity,
+    pub position: TilePos,
+}
+
+/// A stack of items lying at, or stored at, a tile
+#[derive(Deserialize, Serialize)]
+pub struct SavedItems {
+    pub position: TilePos,
+    pub stack: ItemStack,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+    InvalidTerrain,
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "{}", err),
+            SaveError::Format(err) => write!(f, "{}", err),
+            SaveError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "save version {} is not supported (expected {})",
+                    version, SAVE_VERSION
+                )
+            }
+            SaveError::InvalidTerrain => write!(f, "terrain does not match the tilemap size"),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<std::io::Error> for SaveError {
+    fn from(err: std::io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(err: serde_json::Error) -> Self {
+        SaveError::Format(err)
+    }
+}
+
+impl SaveFile {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, SaveError> {
+        let save: SaveFile = serde_json::from_slice(&std::fs::read(path)?)?;
+
+        if save.version != SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion(save.version));
+        }
+
+        if save.terrain.len() != TILEMAP_SIZE.count() {
+            return Err(SaveError::InvalidTerrain);
+        }
+
+        Ok(save)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+fn save_hotkey_system(keys: Res<ButtonInput<KeyCode>>, mut save_writer: EventWriter<SaveColony>) {
+    if keys.just_pressed(KeyCode::F5) {
+        save_writer.send(SaveColony);
+    }
+}
+
+fn save_system(
+    mut events: EventReader<SaveColony>,
+    time: Res<Time>,
+    seed: Res<WorldSeed>,
+    world: Res<World>,
+    ledger: Res<ReservationLedger>,
+    board: Res<JobBoard>,
+    jobs: Query<&Job>,
+    colony: Res<ColonyBlackboard>,
+    tilemaps: Query<(&Name, &TileStorage, &TilemapTexture, &Transform), Without<ReservationTilemap>>,
+    tiles: Query<(
+        &TilePos,
+        &TileTextureIndex,
+        Option<&AnimatedTile>,
+        Has<Bush>,
+        Has<Rock>,
+        Has<Reservable>,
+        Has<Reserved>,
+    )>,
+    villagers: Query<
+        (
+            Entity,
+            &Transform,
+            &Movement,
+            &Blackboard,
+            &Needs,
+            &Inventory,
+            &WorkPriorities,
+            &Skills,
+            &Profession,
+            Option<&Order>,
+        ),
+        With<HasThinker>,
+    >,
+    stockpiles: Query<(&Stockpile, &Children)>,
+    cells: Query<(Entity, &TilePos, &StockpileCell)>,
+    loose_items: Query<(&TilePos, &LooseItem)>,
+    beds: Query<(Entity, &TilePos), With<Bed>>,
+    capacities: Query<&Capacity>,
+) {
+    if events.read().last().is_none() {
+        return;
+    }
+
+    let start = std::time::Instant::now();
+    let now = time.elapsed_seconds();
+
+    let mut saved_tilemaps = vec![];
+    for (name, storage, texture, transform) in tilemaps.iter() {
+        let TilemapTexture::Single(handle) = texture else {
+            continue;
+        };
+
+        let Some(texture_path) = handle.path() else {
+            warn!("Skipping tilemap {} because its texture has no path", name);
+            continue;
+        };
+
+        let saved_tiles = storage
+            .iter()
+            .flatten()
+            .filter_map(|&tile| tiles.get(tile).ok().map(|components| (tile, components)))
+            .map(
+                |(entity, (&position, texture_index, animation, bush, rock, reservable, reserved))| SavedTile {
+                    entity,
+                    position,
+                    texture_index: texture_index.0,
+                    animation: animation.copied(),
+                    bush,
+                    rock,
+                    reservable,
+                    reserved,
+                    job: board
+                        .job_for(entity)
+                        .and_then(|job| jobs.get(job).ok())
+                        .map(|job| SavedJob {
+                            kind: job.kind,
+                            priority: job.priority,
+                            required_skill: job.required_skill,
+                        }),
+                },
+            )
+            .collect();
+
+        saved_tilemaps.push(SavedTilemap {
+            name: name.to_string(),
+            texture: texture_path.to_string(),
+            z: transform.translation.z,
+            tiles: saved_tiles,
+        });
+    }
+
+    let saved_villagers = villagers
+        .iter()
+        .map(
+            |(
+                villager,
+                transform,
+                movement,
+                blackboard,
+                needs,
+                inventory,
+                work_priorities,
+                skills,
+                profession,
+                order,
+            )| {
+                SavedVillager {
+                    entity: villager,
+                    position: transform.translation.xy().to_array(),
+                    path: movement.path.clone(),
+                    blackboard: blackboard.clone(),
+                    needs: needs.clone(),
+                    inventory: inventory.clone(),
+                    work_priorities: work_priorities.clone(),
+                    skills: skills.clone(),
+                    profession: profession.clone(),
+                    reservations: ledger
+                        .reservations_of(villager)
+                        .map(|reservation| SavedReservation {
+                            target: reservation.target,
+                            capacity: capacity_of(reservation.target, &capacities),
+                            ttl: reservation.ttl,
+                            remaining: reservation.ttl.map(|ttl| ttl - (now - reservation.created)),
+                        })
+                        .collect(),
+                    order: order.and_then(|&order| match order {
+                        Order::MoveTo(tilepos) => Some(SavedOrder::MoveTo(tilepos)),
+                        Order::Gather(bush) => tiles.get(bush).ok().map(|(&tilepos, ..)| SavedOrder::Gather(tilepos)),
+                    }),
+                }
+            },
+        )
+        .collect();
+
+    let save = SaveFile {
+        version: SAVE_VERSION,
+        seed: seed.0,
+        terrain: world.tiles.iter().copied().collect(),
+        tilemaps: saved_tilemaps,
+        villagers: saved_villagers,
+        stockpiles: stockpiles
+            .iter()
+            .map(|(stockpile, children)| SavedStockpile {
+                min: stockpile.min,
+                max: stockpile.max,
+                allowed: stockpile.allowed.clone(),
+                cells: cells
+                    .iter_many(children)
+                    .map(|(entity, &position, cell)| SavedCell {
+                        entity,
+                        position,
+                        contents: cell.contents,
+                    })
+                    .collect(),
+            })
+            .collect(),
+        loose_items: loose_items
+            .iter()
+            .map(|(&position, loose_item)| SavedItems {
+                position,
+                stack: loose_item.0,
+            })
+            .collect(),
+        beds: beds
+            .iter()
+            .map(|(entity, &position)| SavedBed { entity, position })
+            .collect(),
+        colony: colony.0.clone(),
+    };
+
+    match save.write(SAVE_PATH) {
+        Ok(()) => info!("Saved colony to {} in {}ms", SAVE_PATH, start.elapsed().as_millis()),
+        Err(err) => error!("Failed to save colony to {}: {}", SAVE_PATH, err),
+    }
+}
+
+fn load_system(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut ledger: ResMut<ReservationLedger>,
+    mut board: ResMut<JobBoard>,
+    mut events: EventReader<LoadColony>,
+    assets: Res<AssetServer>,
+    images: Res<CharacterAssets>,
+    mut next_state: ResMut<NextState<crate::states::States>>,
+) {
+    if events.read().last().is_none() {
+        return;
+    }
+
+    let save = match SaveFile::read(SAVE_PATH) {
+        Ok(save) => save,
+        Err(err) => {
+            error!("Failed to load colony from {}: {}", SAVE_PATH, err);
+            return;
+        }
+    };
+
+    commands.insert_resource(WorldSeed(save.seed));
+    commands.insert_resource(World {
+        tiles: Grid::new_iterator(Size::new(TILEMAP_SIZE.x, TILEMAP_SIZE.y), save.terrain.into_iter()),
+    });
+
+    // Orders point at bushes, so remember where each restored bush ended up
+    let mut bushes = HashMap::new();
+
+    // Blackboards and reservations point at the entities that were saved, so remember what each became
+    let mut entities = HashMap::new();
+
+    for saved_tilemap in save.tilemaps {
+        let tilemap_entity = commands.spawn_empty().id();
+        let mut tile_storage = TileStorage::empty(TILEMAP_SIZE);
+        let mut children = vec![];
+
+        for saved_tile in saved_tilemap.tiles {
+            let mut tile = commands.spawn((
+                Name::new("Tile"),
+                TileBundle {
+                    position: saved_tile.position,
+                    texture_index: TileTextureIndex(saved_tile.texture_index),
+                    tilemap_id: TilemapId(tilemap_entity),
+                    ..Default::default()
+                },
+            ));
+
+            if let Some(animation) = saved_tile.animation {
+                tile.insert(animation);
+            }
+
+            if saved_tile.bush {
+                tile.insert(bush_bundle(saved_tile.position));
+                bushes.insert(saved_tile.position, tile.id());
+            }
+
+            if saved_tile.rock {
+                tile.insert(rock_bundle(saved_tile.position));
+            }
+
+            // Reserved tiles are reserved again once the villager holding them is restored, or stay designated
+            if saved_tile.reservable || saved_tile.reserved {
+                tile.insert(Reservable);
+            }
+
+            let target = tile.id();
+            if let Some(saved_job) = saved_tile.job {
+                // Posted here rather than by the job board, which would post it at the default priority
+                let job = Job {
+                    kind: saved_job.kind,
+                    priority: saved_job.priority,
+                    required_skill: saved_job.required_skill,
+                    location: saved_tile.position,
+                    target,
+                };
+                board.post(&mut commands, job);
+            }
+
+            entities.insert(saved_tile.entity, target);
+            tile_storage.set(&saved_tile.position, target);
+            children.push(target);
+        }
+
+        commands
+            .entity(tilemap_entity)
+            .insert((
+                Name::new(saved_tilemap.name),
+                TilemapBundle {
+                    grid_size: TILEMAP_TILE_SIZE.into(),
+                    map_type: TILEMAP_TYPE,
+                    size: TILEMAP_SIZE,
+                    storage: tile_storage,
+                    texture: TilemapTexture::Single(assets.load(saved_tilemap.texture)),
+                    tile_size: TILEMAP_TILE_SIZE,
+                    transform: Transform::from_xyz(0.0, 0.0, saved_tilemap.z),
+                    ..Default::default()
+                },
+            ))
+            .push_children(&children);
+    }
+
+    let villagers: Vec<_> = save
+        .villagers
+        .into_iter()
+        .map(|saved_villager| {
+            let [x, y] = saved_villager.position;
+            let villager = spawn_villager(&mut commands, Some(&images), Transform::from_xyz(x, y, 10.0));
+            entities.insert(saved_villager.entity, villager);
+            (villager, saved_villager)
+        })
+        .collect();
+
+    for saved_stockpile in save.stockpiles {
+        let stockpile = Stockpile {
+            min: saved_stockpile.min,
+            max: saved_stockpile.max,
+            allowed: saved_stockpile.allowed,
+        };
+        let contents: HashMap<TilePos, Option<ItemStack>> = saved_stockpile
+            .cells
+            .iter()
+            .map(|saved_cell| (saved_cell.position, saved_cell.contents))
+            .collect();
+        let (_, cells) = spawn_stockpile(&mut commands, stockpile, |tilepos| {
+            contents.get(&tilepos).copied().flatten()
+        });
+
+        for saved_cell in saved_stockpile.cells {
+            if let Some(&cell) = cells.get(&saved_cell.position) {
+                entities.insert(saved_cell.entity, cell);
+            }
+        }
+    }
+
+    for saved_bed in save.beds {
+        let bed = spawn_bed(&mut commands, saved_bed.position);
+        entities.insert(saved_bed.entity, bed);
+    }
+
+    for (villager, mut saved_villager) in villagers {
+        // Entities that were not saved are forgotten rather than left pointing at whatever reuses their id
+        saved_villager
+            .blackboard
+            .map_entities(|entity| entities.get(&entity).copied());
+
+        commands.entity(villager).insert((
+            Movement::new(saved_villager.path),
+            saved_villager.blackboard,
+            saved_villager.needs,
+            saved_villager.inventory,
+            saved_villager.work_priorities,
+            saved_villager.skills,
+            saved_villager.profession,
+        ));
+
+        for saved_reservation in saved_villager.reservations {
+            let target = match saved_reservation.target {
+                ReservationTarget::Entity(entity) => match entities.get(&entity) {
+                    Some(&entity) => ReservationTarget::Entity(entity),
+                    // The target was not saved, such as a bush that was gathered
+                    None => continue,
+                },
+                ReservationTarget::Tile(tilepos) => ReservationTarget::Tile(tilepos),
+            };
+
+            // No action holds the reservation until the rebuilt thinker picks one that asks for it again
+            let elapsed = saved_reservation.ttl.zip(saved_reservation.remaining);
+            let reservation = Reservation {
+                owner: villager,
+                target,
+                created: time.elapsed_seconds() - elapsed.map_or(0.0, |(ttl, remaining)| ttl - remaining),
+                ttl: saved_reservation.ttl,
+                actions: vec![],
+            };
+            ledger.reserve(reservation, saved_reservation.capacity);
+        }
+
+        let order = saved_villager.order.and_then(|order| match order {
+            SavedOrder::MoveTo(tilepos) => Some(Order::MoveTo(tilepos)),
+            // The bush may have been gathered by the time the colony was saved
+            SavedOrder::Gather(tilepos) => bushes.get(&tilepos).map(|&bush| Order::Gather(bush)),
+        });
+        if let Some(order) = order {
+            commands.entity(villager).insert(order);
+        }
+    }
+
+    let mut colony = save.colony;
+    colony.map_entities(|entity| entities.get(&entity).copied());
+    commands.insert_resource(ColonyBlackboard(colony));
+
+    for saved_items in save.loose_items {
+        spawn_loose_item(&mut commands, saved_items.position, saved_items.stack);
+    }
+
+    info!("Loaded colony from {}", SAVE_PATH);
+    next_state.set(Play);
+}
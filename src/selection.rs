@@ -0,0 +1,121 @@
+use crate::agent::Bush;
+use crate::designation::DesignationEvent;
+use crate::orders::Order;
+use crate::states::States::Play;
+use crate::worldgen::{TILEMAP_SIZE, TILEMAP_TILE_SIZE, TILEMAP_TYPE};
+use crate::ENTITY_SIZE_IN_PIXELS;
+use bevy::input::mouse::MouseButtonInput;
+use bevy::input::ButtonState;
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::TilePos;
+use big_brain::prelude::HasThinker;
+
+/// Selecting villagers with the marquee and giving them orders with the right mouse button
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (select_villagers_system, order_system, draw_selection_system).run_if(in_state(Play)),
+        );
+    }
+}
+
+/// Tag for the villagers the player has selected
+#[derive(Component)]
+pub struct Selected;
+
+/// Replaces the selection with the villagers caught by the marquee.
+///
+/// Dragging over no villagers, for example to designate bushes, leaves the selection alone, while clicking on empty
+/// ground clears it.
+fn select_villagers_system(
+    mut commands: Commands,
+    mut events: EventReader<DesignationEvent>,
+    villagers: Query<(), With<HasThinker>>,
+    selected: Query<Entity, With<Selected>>,
+) {
+    for event in events.read() {
+        let caught: Vec<Entity> = event
+            .entities
+            .iter()
+            .copied()
+            .filter(|&entity| villagers.contains(entity))
+            .collect();
+
+        let click = event.rect.size().max_element() < ENTITY_SIZE_IN_PIXELS;
+        if caught.is_empty() && !click {
+            continue;
+        }
+
+        for entity in selected.iter() {
+            commands.entity(entity).remove::<Selected>();
+        }
+
+        for entity in caught {
+            commands.entity(entity).insert(Selected);
+        }
+    }
+}
+
+/// Right clicking a bush orders the closest selected villager to gather it, which also prioritizes a bush that was
+/// already designated, and right clicking anywhere else orders all of them to walk there
+fn order_system(
+    mut commands: Commands,
+    mut events: EventReader<MouseButtonInput>,
+    q_window: Query<&Window>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    bushes: Query<(Entity, &TilePos), With<Bush>>,
+    selected: Query<(Entity, &GlobalTransform), With<Selected>>,
+) {
+    for event in events.read() {
+        if event.button != MouseButton::Right || event.state != ButtonState::Pressed || selected.is_empty() {
+            continue;
+        }
+
+        let (camera, camera_transform) = q_camera.single();
+        let Some(tilepos) = q_window
+            .single()
+            .cursor_position()
+            .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor))
+            .and_then(|position| {
+                TilePos::from_world_pos(&position, &TILEMAP_SIZE, &TILEMAP_TILE_SIZE.into(), &TILEMAP_TYPE)
+            })
+        else {
+            continue;
+        };
+
+        match bushes.iter().find(|(_, &bush_tilepos)| bush_tilepos == tilepos) {
+            Some((bush, _)) => {
+                // A bush can only be gathered by one villager, so the closest one goes
+                let target = tilepos.center_in_world(&TILEMAP_TILE_SIZE.into(), &TILEMAP_TYPE);
+                let closest = selected.iter().min_by(|(_, a), (_, b)| {
+                    let a = a.translation().xy().distance_squared(target);
+                    let b = b.translation().xy().distance_squared(target);
+                    a.total_cmp(&b)
+                });
+
+                if let Some((entity, _)) = closest {
+                    commands.entity(entity).insert(Order::Gather(bush));
+                }
+            }
+            None => {
+                for (entity, _) in selected.iter() {
+                    commands.entity(entity).insert(Order::MoveTo(tilepos));
+                }
+            }
+        }
+    }
+}
+
+fn draw_selection_system(mut gizmos: Gizmos, selected: Query<&GlobalTransform, With<Selected>>) {
+    for transform in selected.iter() {
+        gizmos.rect_2d(
+            transform.translation().xy(),
+            0.0,
+            Vec2::splat(ENTITY_SIZE_IN_PIXELS),
+            Color::srgb_u8(181, 137, 0), // Solarized Yellow
+        );
+    }
+}
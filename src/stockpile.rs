@@ -0,0 +1,590 @@
+use crate::behavior::BehaviorAppExt;
+use crate::designation::{DesignationEvent, DesignationKind};
+use crate::ext::{TilePosExt, Vec2Ext};
+use crate::items::{Inventory, Item, ItemStack};
+use crate::jobs::{JobKind, WorkPriorities};
+use crate::navigation::{PathQuery, Pathfinder, Regions};
+use crate::reservations::{
+    Capacity, ReleaseReason, ReleaseReservation, ReservationLedger, ReservationRequest, ReservationRequestBuilder,
+    Reserved,
+};
+use crate::states::States::Play;
+use crate::villager::{Movement, MovementCosts, PathNotFound};
+use crate::worldgen::{World, TILEMAP_SIZE, TILEMAP_TILE_SIZE};
+use crate::SimulationSet;
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::TilePos;
+use big_brain::prelude::*;
+use std::collections::HashMap;
+
+/// How strongly a villager with nothing else to do wants to tidy up loose items
+const IDLE_HAUL_SCORE: f32 = 0.3;
+
+/// How many haulers can be on their way to a stockpile cell at once
+const HAULERS_PER_CELL: usize = 3;
+
+pub struct StockpilePlugin;
+
+impl Plugin for StockpilePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ZoneFilter>()
+            .register_scorer("Haul", HaulScorer)
+            .register_action("Haul", HaulAction::default())
+            .add_systems(
+                FixedUpdate,
+                (
+                    haul_action_system.in_set(SimulationSet::Haul),
+                    haul_scorer_system.in_set(BigBrainSet::Scorers),
+                )
+                    .run_if(in_state(Play)),
+            )
+            .add_systems(Update, designate_stockpile_system.run_if(in_state(Play)));
+    }
+}
+
+/// Outlines stockpile zones and marks loose items
+pub struct StockpileRenderPlugin;
+
+impl Plugin for StockpileRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, draw_stockpiles_system.run_if(in_state(Play)));
+    }
+}
+
+/// Buttons for picking which items newly designated stockpile zones accept
+pub struct ZoneFilterToolbarPlugin;
+
+impl Plugin for ZoneFilterToolbarPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(Play), setup_zone_filter_toolbar).add_systems(
+            Update,
+            (zone_filter_button_system, zone_filter_style_system)
+                .chain()
+                .run_if(in_state(Play)),
+        );
+    }
+}
+
+/// The kinds of items that stockpile zones accept when they are designated
+#[derive(Reflect, Resource)]
+#[reflect(Resource)]
+pub struct ZoneFilter {
+    pub allowed: Vec<Item>,
+}
+
+impl Default for ZoneFilter {
+    fn default() -> Self {
+        Self {
+            allowed: Item::ALL.to_vec(),
+        }
+    }
+}
+
+impl ZoneFilter {
+    /// Allows the item if it was not allowed, and the other way around
+    pub fn toggle(&mut self, item: Item) {
+        if let Some(index) = self.allowed.iter().position(|&allowed| allowed == item) {
+            self.allowed.remove(index);
+        } else {
+            self.allowed.push(item);
+        }
+    }
+}
+
+/// A zone of cells where hauled items are stored
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct Stockpile {
+    pub min: TilePos,
+    pub max: TilePos,
+    /// The kinds of items haulers may bring here
+    pub allowed: Vec<Item>,
+}
+
+impl Stockpile {
+    /// A zone covering the tiles from `min` to `max` inclusive that accepts every kind of item
+    pub fn new(min: TilePos, max: TilePos) -> Self {
+        Self {
+            min,
+            max,
+            allowed: Item::ALL.to_vec(),
+        }
+    }
+
+    pub fn accepts(&self, item: Item) -> bool {
+        self.allowed.contains(&item)
+    }
+
+    /// Returns every tile of the zone.
+    pub fn tiles(&self) -> Vec<TilePos> {
+        (self.min.y..=self.max.y)
+            .flat_map(|y| (self.min.x..=self.max.x).map(move |x| TilePos { x, y }))
+            .collect()
+    }
+}
+
+/// One tile of a stockpile zone, holding at most a single stack
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct StockpileCell {
+    pub zone: Entity,
+    pub contents: Option<ItemStack>,
+}
+
+impl StockpileCell {
+    /// Returns how many more items of a kind this cell can hold, ignoring the zone's filter.
+    pub fn space_for(&self, item: Item) -> u32 {
+        match self.contents {
+            None => item.definition().stack_size,
+            Some(stack) if stack.item == item => item.definition().stack_size - stack.count,
+            Some(_) => 0,
+        }
+    }
+
+    /// Returns the kind of item stored here if it can be eaten.
+    pub fn edible(&self) -> Option<Item> {
+        self.contents
+            .map(|stack| stack.item)
+            .filter(|item| item.definition().edible)
+    }
+
+    /// Takes up to `count` items out of the cell, returning how many were taken.
+    pub fn take(&mut self, count: u32) -> u32 {
+        let Some(stack) = &mut self.contents else {
+            return 0;
+        };
+
+        let taken = count.min(stack.count);
+        stack.count -= taken;
+        if stack.count == 0 {
+            self.contents = None;
+        }
+
+        taken
+    }
+}
+
+/// Items lying on the ground, waiting to be hauled
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct LooseItem(pub ItemStack);
+
+/// Spawns a stockpile zone with a cell for each of its tiles, filling the cells with `contents`.
+///
+/// Returns the zone and the cell spawned at each tile.
+pub fn spawn_stockpile(
+    commands: &mut Commands,
+    stockpile: Stockpile,
+    mut contents: impl FnMut(TilePos) -> Option<ItemStack>,
+) -> (Entity, HashMap<TilePos, Entity>) {
+    let (min, max) = (stockpile.min, stockpile.max);
+    let zone = commands.spawn((Name::new("Stockpile"), stockpile)).id();
+    let mut cells = HashMap::new();
+
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let tilepos = TilePos { x, y };
+            let cell = commands
+                .spawn((
+                    Name::new("StockpileCell"),
+                    StockpileCell {
+                        zone,
+                        contents: contents(tilepos),
+                    },
+                    Capacity(HAULERS_PER_CELL),
+                    tilepos,
+                    TransformBundle::from(Transform::from_translation(tilepos.to_world_space().extend(0.0))),
+                ))
+                .id();
+            commands.entity(zone).add_child(cell);
+            cells.insert(tilepos, cell);
+        }
+    }
+
+    (zone, cells)
+}
+
+/// Spawns items on the ground at a tile
+pub fn spawn_loose_item(commands: &mut Commands, tilepos: TilePos, stack: ItemStack) -> Entity {
+    commands
+        .spawn((
+            Name::new("LooseItem"),
+            LooseItem(stack),
+            tilepos,
+            TransformBundle::from(Transform::from_translation(tilepos.to_world_space().extend(0.0))),
+        ))
+        .id()
+}
+
+/// Turns the area covered by zone designations into stockpiles
+fn designate_stockpile_system(
+    mut commands: Commands,
+    filter: Res<ZoneFilter>,
+    mut events: EventReader<DesignationEvent>,
+) {
+    for DesignationEvent { rect, .. } in events.read().filter(|event| event.kind == DesignationKind::Zone) {
+        // Clamp to the map so that a marquee dragged past the edge still makes a zone. Tiles are centered on their
+        // world position, so the last tile ends half a tile before the edge of the map.
+        let tile_size = Vec2::from(TILEMAP_TILE_SIZE);
+        let map_max = Vec2::new(TILEMAP_SIZE.x as f32, TILEMAP_SIZE.y as f32) * tile_size - tile_size / 2.0 - 1.0;
+        let min = rect.min.clamp(Vec2::ZERO, map_max).to_tilepos();
+        let max = rect.max.clamp(Vec2::ZERO, map_max).to_tilepos();
+
+        let stockpile = Stockpile {
+            allowed: filter.allowed.clone(),
+            ..Stockpile::new(min, max)
+        };
+        spawn_stockpile(&mut commands, stockpile, |_| None);
+    }
+}
+
+#[derive(Component)]
+struct ZoneFilterButton(Item);
+
+fn setup_zone_filter_toolbar(mut commands: Commands) {
+    commands
+        .spawn((
+            Name::new("Zone Filter Toolbar"),
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    // Sits just above the designation toolbar
+                    bottom: Val::Px(40.0),
+                    left: Val::Px(8.0),
+                    column_gap: Val::Px(4.0),
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|toolbar| {
+            for item in Item::ALL {
+                toolbar
+                    .spawn((
+                        ZoneFilterButton(item),
+                        ButtonBundle {
+                            style: Style {
+                                padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                    ))
+                    .with_children(|button| {
+                        button.spawn(TextBundle::from_section(
+                            item.definition().name,
+                            TextStyle {
+                                font_size: 16.0,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        ));
+                    });
+            }
+        });
+}
+
+fn zone_filter_button_system(
+    buttons: Query<(&Interaction, &ZoneFilterButton), Changed<Interaction>>,
+    mut filter: ResMut<ZoneFilter>,
+) {
+    for (interaction, button) in buttons.iter() {
+        if *interaction == Interaction::Pressed {
+            filter.toggle(button.0);
+        }
+    }
+}
+
+/// Highlights the buttons of the items new zones accept
+fn zone_filter_style_system(filter: Res<ZoneFilter>, mut buttons: Query<(&ZoneFilterButton, &mut BackgroundColor)>) {
+    for (button, mut background) in buttons.iter_mut() {
+        *background = if filter.allowed.contains(&button.0) {
+            BackgroundColor(Color::srgb_u8(133, 153, 0)) // Solarized Green
+        } else {
+            BackgroundColor(Color::srgb_u8(7, 54, 66)) // Solarized Base02
+        };
+    }
+}
+
+#[derive(Clone, Component, Debug, ScorerBuilder)]
+pub struct HaulScorer;
+
+/// Scores hauling by how full the actor's inventory is, and a little when there are loose items to tidy up, weighed
+/// by how highly the actor prioritizes hauling
+pub fn haul_scorer_system(
+    agents: Query<(&Inventory, &WorkPriorities)>,
+    loose_items: Query<&LooseItem>,
+    cells: Query<&StockpileCell, Without<Reserved>>,
+    zones: Query<&Stockpile>,
+    mut query: Query<(&Actor, &mut Score), With<HaulScorer>>,
+) {
+    let accepts = |item: Item| {
+        cells
+            .iter()
+            .any(|cell| cell.space_for(item) > 0 && zones.get(cell.zone).is_ok_and(|zone| zone.accepts(item)))
+    };
+
+    for (Actor(actor), mut score) in &mut query {
+        let Ok((inventory, priorities)) = agents.get(*actor) else {
+            score.set(0.0);
+            continue;
+        };
+
+        if inventory.stacks().iter().any(|stack| accepts(stack.item)) {
+            let used_slots = inventory.stacks().len() as f32 / inventory.slots as f32;
+            let used_weight = inventory.weight() / inventory.max_weight;
+            let fullness = used_slots.max(used_weight).min(1.0);
+            score.set(priorities.weigh(JobKind::Haul, IDLE_HAUL_SCORE + (1.0 - IDLE_HAUL_SCORE) * fullness));
+        } else if inventory.is_empty() && loose_items.iter().any(|loose_item| accepts(loose_item.0.item)) {
+            score.set(priorities.weigh(JobKind::Haul, IDLE_HAUL_SCORE));
+        } else {
+            score.set(0.0);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum HaulTarget {
+    /// Pick up the loose items on this entity
+    Pickup(Entity, TilePos),
+    /// Deliver carried items to this stockpile cell
+    Deliver(Entity, TilePos),
+}
+
+/// Picks up loose items when empty handed, otherwise delivers carried items to the nearest accepting stockpile cell.
+///
+/// Cells are reserved before walking to them, so that no more than `HAULERS_PER_CELL` haulers head for the same one.
+#[derive(Clone, Component, Debug, Default, ActionBuilder)]
+pub struct HaulAction {
+    target: Option<HaulTarget>,
+}
+
+pub fn haul_action_system(
+    mut commands: Commands,
+    world: Res<World>,
+    costs: Res<MovementCosts>,
+    mut pathfinder: ResMut<Pathfinder>,
+    regions: Res<Regions>,
+    ledger: Res<ReservationLedger>,
+    mut agents: Query<(&Transform, &mut Movement, &mut Inventory)>,
+    mut loose_items: Query<(Entity, &TilePos, &mut LooseItem)>,
+    mut cells: Query<(Entity, &TilePos, &mut StockpileCell, Has<Reserved>)>,
+    zones: Query<&Stockpile>,
+    mut action_query: Query<(Entity, &Actor, &mut ActionState, &mut HaulAction, &ActionSpan)>,
+    mut reservation_request_writer: EventWriter<ReservationRequest>,
+    mut release_writer: EventWriter<ReleaseReservation>,
+    mut path_not_found_writer: EventWriter<PathNotFound>,
+) {
+    for (action, actor, mut action_state, mut haul, span) in &mut action_query {
+        let _guard = span.span().enter();
+
+        let Ok((transform, mut movement, mut inventory)) = agents.get_mut(actor.0) else {
+            *action_state = ActionState::Failure;
+            continue;
+        };
+
+        let position = transform.translation.xy().to_tilepos();
+
+        match *action_state {
+            ActionState::Requested => {
+                let target = match haul.target {
+                    Some(target) => target,
+                    None if inventory.is_empty() => {
+                        let nearest = loose_items
+                            .iter()
+                            .filter(|(_, &tilepos, _)| regions.same_region(position, tilepos))
+                            .min_by_key(|(_, &tilepos, _)| distance_squared(position, tilepos));
+
+                        let Some((item_entity, &tilepos, _)) = nearest else {
+                            *action_state = ActionState::Failure;
+                            continue;
+                        };
+
+                        *haul.target.insert(HaulTarget::Pickup(item_entity, tilepos))
+                    }
+                    None => {
+                        let takes_some = |cell: &StockpileCell| {
+                            inventory.stacks().iter().any(|stack| {
+                                cell.space_for(stack.item) > 0
+                                    && zones.get(cell.zone).is_ok_and(|zone| zone.accepts(stack.item))
+                            })
+                        };
+
+                        // A cell still held from before the colony was loaded is delivered to first
+                        let held = ledger
+                            .reservations_of(actor.0)
+                            .filter_map(|reservation| cells.get(reservation.target.entity()?).ok())
+                            .find(|(_, _, cell, _)| takes_some(cell));
+                        let nearest = held.or_else(|| {
+                            cells
+                                .iter()
+                                .filter(|(_, &tilepos, cell, reserved)| {
+                                    !reserved && regions.same_region(position, tilepos) && takes_some(cell)
+                                })
+                                .min_by_key(|(_, &tilepos, _, _)| distance_squared(position, tilepos))
+                        });
+
+                        let Some((cell_entity, &tilepos, _, _)) = nearest else {
+                            *action_state = ActionState::Failure;
+                            continue;
+                        };
+
+                        reservation_request_writer.send(
+                            ReservationRequestBuilder::default()
+                                .requester(actor.0)
+                                .action(action)
+                                .target(cell_entity)
+                                .build()
+                                .unwrap(),
+                        );
+
+                        *haul.target.insert(HaulTarget::Deliver(cell_entity, tilepos))
+                    }
+                };
+
+                let (goal, route) = match target {
+                    HaulTarget::Pickup(_, goal) => (goal, pathfinder.query(position, goal)),
+                    HaulTarget::Deliver(cell_entity, goal) => {
+                        if ledger.is_reserved_by(cell_entity, actor.0) {
+                            // Everyone delivering to the same zone follows one flow field towards it
+                            let zone = cells
+                                .get(cell_entity)
+                                .ok()
+                                .and_then(|(_, _, cell, _)| zones.get(cell.zone).ok());
+                            let path = zone
+                                .and_then(|zone| pathfinder.path_into(&world, &costs, position, &zone.tiles(), goal));
+                            (goal, path.map_or(PathQuery::NotFound, PathQuery::Found))
+                        } else if cells.get(cell_entity).is_ok_and(|(_, _, _, reserved)| reserved) {
+                            // Someone else got there first, pick another cell next frame
+                            haul.target = None;
+                            continue;
+                        } else {
+                            // Still waiting for the reservation to go through
+                            continue;
+                        }
+                    }
+                };
+
+                match route {
+                    PathQuery::Found(mut path) => {
+                        if path.first() == Some(&position) {
+                            path.remove(0);
+                        }
+
+                        movement.path = path;
+                        *action_state = ActionState::Executing;
+                    }
+                    PathQuery::NotFound => {
+                        path_not_found_writer.send(PathNotFound {
+                            agent: actor.0,
+                            start: position,
+                            goal,
+                        });
+                        release(&mut release_writer, actor.0, &mut haul, ReleaseReason::Failed);
+                        *action_state = ActionState::Failure;
+                    }
+                    PathQuery::Pending => {}
+                }
+            }
+            ActionState::Executing => {
+                let goal = match haul.target {
+                    Some(HaulTarget::Pickup(_, goal) | HaulTarget::Deliver(_, goal)) => goal,
+                    None => {
+                        *action_state = ActionState::Failure;
+                        continue;
+                    }
+                };
+
+                if !movement.path.is_empty() || position != goal {
+                    // Movement should be handled by the movement system
+                    continue;
+                }
+
+                match haul.target {
+                    Some(HaulTarget::Pickup(item_entity, _)) => {
+                        if let Ok((_, _, mut loose_item)) = loose_items.get_mut(item_entity) {
+                            let left_over = inventory.add(loose_item.0.item, loose_item.0.count);
+                            if left_over == 0 {
+                                commands.entity(item_entity).despawn();
+                            } else {
+                                loose_item.0.count = left_over;
+                            }
+                        }
+                    }
+                    Some(HaulTarget::Deliver(cell_entity, _)) => {
+                        if let Ok((_, _, mut cell, _)) = cells.get_mut(cell_entity) {
+                            let zone = zones.get(cell.zone).ok();
+                            let deliverable =
+                                inventory.stacks().iter().map(|stack| stack.item).find(|&item| {
+                                    cell.space_for(item) > 0 && zone.is_some_and(|zone| zone.accepts(item))
+                                });
+
+                            if let Some(item) = deliverable {
+                                let count = inventory.remove(item, cell.space_for(item));
+                                let stored = cell.contents.map_or(0, |stack| stack.count);
+                                cell.contents = Some(ItemStack {
+                                    item,
+                                    count: stored + count,
+                                });
+                            }
+                        }
+                    }
+                    None => {}
+                }
+
+                release(&mut release_writer, actor.0, &mut haul, ReleaseReason::Done);
+                *action_state = ActionState::Success;
+            }
+            ActionState::Cancelled => {
+                release(&mut release_writer, actor.0, &mut haul, ReleaseReason::Failed);
+                *action_state = ActionState::Failure;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Forgets the haul target, giving up the reservation on its cell
+fn release(
+    release_writer: &mut EventWriter<ReleaseReservation>,
+    agent: Entity,
+    haul: &mut HaulAction,
+    reason: ReleaseReason,
+) {
+    if let Some(HaulTarget::Deliver(cell, _)) = haul.target.take() {
+        release_writer.send(ReleaseReservation {
+            owner: agent,
+            target: Some(cell.into()),
+            reason,
+        });
+    }
+}
+
+fn distance_squared(a: TilePos, b: TilePos) -> u32 {
+    a.x.abs_diff(b.x).pow(2) + a.y.abs_diff(b.y).pow(2)
+}
+
+fn draw_stockpiles_system(
+    mut gizmos: Gizmos,
+    zones: Query<&Stockpile>,
+    cells: Query<(&TilePos, &StockpileCell)>,
+    loose_items: Query<&TilePos, With<LooseItem>>,
+) {
+    let tile_size = Vec2::from(TILEMAP_TILE_SIZE);
+
+    for zone in zones.iter() {
+        let min = zone.min.to_world_space() - tile_size / 2.0;
+        let max = zone.max.to_world_space() + tile_size / 2.0;
+        gizmos.rect_2d((min + max) / 2.0, 0.0, max - min, Color::srgb_u8(38, 139, 210));
+    }
+
+    for (tilepos, cell) in cells.iter() {
+        if cell.contents.is_some() {
+            gizmos.circle_2d(tilepos.to_world_space(), tile_size.x / 4.0, Color::srgb_u8(133, 153, 0));
+        }
+    }
+
+    for tilepos in loose_items.iter() {
+        gizmos.circle_2d(tilepos.to_world_space(), tile_size.x / 4.0, Color::srgb_u8(203, 75, 22));
+    }
+}
@@ -1,91 +1,392 @@
-use crate::agent::{Bush, GatherAction, MoveToNearest, WorkNeedScorer};
 use crate::animation::AnimationBundle;
 use crate::assets::CharacterAssets;
+use crate::behavior::Profession;
 use crate::blackboard::Blackboard;
 use crate::ext::*;
-use crate::states::States::Play;
-use crate::worldgen::TILEMAP_SIZE;
+use crate::items::Inventory;
+use crate::jobs::{Skill, Skills, WorkPriorities};
+use crate::marquee::{SELECTABLE_GROUP, SELECTION_GROUP};
+use crate::navigation::{PathQuery, Pathfinder};
+use crate::needs::{spawn_bed, Needs};
+use crate::states::States::{LoadPlay, Play};
+use crate::worldgen::{World, TILEMAP_SIZE, TILEMAP_TILE_SIZE, TILEMAP_TYPE};
+use crate::SimulationSet;
+use crate::ENTITY_SIZE_IN_PIXELS;
 use bevy::prelude::*;
 use bevy_ecs_tilemap::helpers::square_grid::neighbors::{Neighbors, SquareDirection};
 use bevy_ecs_tilemap::prelude::TilePos;
-use big_brain::actions::Steps;
-use big_brain::pickers::FirstToScore;
-use big_brain::prelude::Thinker;
+use bevy_rapier2d::geometry::{Collider, CollisionGroups};
+use bevy_spatial::kdtree::KDTree2;
+use bevy_spatial::point::Point2;
+use bevy_spatial::SpatialAccess;
+use kd_tree::KdTree;
 use pathfinding::num_traits::Zero;
 use pathfinding::prelude::astar;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::time::Duration;
 
 pub struct VillagerPlugin;
 
 impl Plugin for VillagerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(Play), setup_villagers)
-            .add_systems(Update, (animate_sprite, movement_system).run_if(in_state(Play)));
+        // Villagers restored from a save are spawned by the save plugin instead
+        app.init_resource::<MovementCosts>()
+            .add_event::<PathNotFound>()
+            .add_event::<PathBlocked>()
+            .init_resource::<CrowdTree>()
+            .add_systems(OnExit(LoadPlay), setup_villagers)
+            .add_systems(Update, animate_sprite.run_if(in_state(Play)))
+            .add_systems(
+                FixedUpdate,
+                (update_crowd_tree_system, movement_system)
+                    .chain()
+                    .in_set(SimulationSet::Move)
+                    .run_if(in_state(Play)),
+            );
     }
 }
 
-pub fn find_path(world: &crate::worldgen::World, start: TilePos, goal: TilePos) -> Option<Vec<TilePos>> {
-    astar(
-        &start,
-        |&current| {
-            let mut next = vec![];
-            let neighbors = Neighbors::get_square_neighboring_positions(&current, &TILEMAP_SIZE, false);
+/// Event published whenever an agent asks for a path that does not exist
+#[derive(Event)]
+pub struct PathNotFound {
+    pub agent: Entity,
+    pub start: TilePos,
+    pub goal: TilePos,
+}
 
-            for &neighbor in neighbors.iter() {
-                let cell = world.wave.grid().get(neighbor.to_coord());
+/// Event published when an agent's path became blocked and no way around was found `MAX_REPATH_ATTEMPTS` times in
+/// a row, after which the agent stops and the action that was moving it fails
+#[derive(Event)]
+pub struct PathBlocked {
+    pub agent: Entity,
+    /// The tile that could no longer be walked onto
+    pub at: TilePos,
+    pub goal: TilePos,
+}
 
-                if let Some(cell) = cell {
-                    let pattern_id = cell.chosen_pattern_id().unwrap();
-                    let value = world.patterns.pattern_top_left_value(pattern_id);
-                    if *value == 255 {
-                        continue;
-                    }
-                }
+/// How many times in a row finding a way around a blocked tile may fail before giving up on the path, searches that
+/// are still running do not count
+const MAX_REPATH_ATTEMPTS: u32 = 3;
+
+/// Every villager that can walk, by position, used to steer villagers around each other
+pub type CrowdTree = KDTree2<Movement>;
+
+/// How close, in world units, another villager has to be to be steered away from
+const SEPARATION_RADIUS: f32 = 12.0;
+
+/// How strongly villagers steer away from each other, compared to walking on
+const SEPARATION_WEIGHT: f32 = 0.6;
+
+/// How far ahead, in world units, a villager looks for someone walking the same way to queue behind
+const QUEUE_DISTANCE: f32 = 10.0;
+
+/// How far to either side, in world units, someone can be and still count as being in the way
+const QUEUE_WIDTH: f32 = 6.0;
 
-                next.push((neighbor, 1));
+/// Villagers stop steering around each other this close, in world units, to the end of their path so that crowds
+/// cannot keep them from arriving
+const ARRIVAL_RADIUS: f32 = 8.0;
+
+/// How many stacks of items a villager can carry
+const VILLAGER_INVENTORY_SLOTS: usize = 4;
+
+/// The heaviest load a villager can carry
+const VILLAGER_MAX_WEIGHT: f32 = 5.0;
+
+/// Where the villagers set up camp, or the nearest walkable tile to it
+const CAMP: TilePos = TilePos { x: 21, y: 26 };
+
+/// The cost of an orthogonal step onto plain ground
+pub const BASE_MOVE_COST: u32 = 10;
+
+/// The `World` tile value of cells that have no ground
+pub const EMPTY_TILE_ID: u16 = 255;
+
+// master.png
+const GRASS_EDGE_TILE_IDS: [u16; 12] = [0, 1, 2, 16, 18, 21, 22, 32, 33, 34, 37, 38];
+// The reeds and lily pads growing in the shallows
+const SHALLOW_WATER_TILE_IDS: [u16; 5] = [166, 167, 168, 169, 170];
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DiagonalMovement {
+    /// Only step north, south, east and west
+    #[default]
+    Never,
+    /// Step diagonally only when both orthogonal tiles beside the step are walkable, so corners are never cut
+    NoCornerCutting,
+    /// Step diagonally whenever the destination tile is walkable
+    Always,
+}
+
+/// The movement rules used by `find_path` and `movement_system`.
+///
+/// Tile costs are keyed on the `World` tile value and are relative to `BASE_MOVE_COST`, so a road could cost 5 and
+/// shallow water 30. Tiles without an entry cost `BASE_MOVE_COST` and tiles mapped to `None` cannot be walked on.
+///
+/// By default only the grass edges and the shallows cost more than plain grass. The tilesets have no sand or road
+/// tiles yet, so those get their costs once they are drawn and placed by worldgen.
+///
+/// # Examples
+///
+/// ```
+/// use bevy_game::villager::{MovementCosts, BASE_MOVE_COST, EMPTY_TILE_ID};
+///
+/// let mut costs = MovementCosts::default();
+/// costs.set_cost(200, Some(2));
+///
+/// assert_eq!(costs.cost(200), Some(2));
+/// assert_eq!(costs.cost(17), Some(BASE_MOVE_COST));
+/// assert_eq!(costs.cost(EMPTY_TILE_ID), None);
+/// ```
+#[derive(Clone, Debug, Resource)]
+pub struct MovementCosts {
+    tiles: HashMap<u16, Option<u32>>,
+    /// The cheapest step onto any walkable tile, kept up to date by `set_cost` for the heuristic
+    min_cost: u32,
+    pub diagonal: DiagonalMovement,
+}
+
+impl MovementCosts {
+    /// Sets the cost of an orthogonal step onto tiles with the given value, `None` making them unwalkable.
+    pub fn set_cost(&mut self, value: u16, cost: Option<u32>) {
+        self.tiles.insert(value, cost);
+        self.min_cost = self
+            .tiles
+            .values()
+            .flatten()
+            .copied()
+            .chain([BASE_MOVE_COST])
+            .min()
+            .unwrap();
+    }
+
+    /// Returns the cost of an orthogonal step onto a tile with the given value, or `None` if it is not walkable.
+    pub fn cost(&self, value: u16) -> Option<u32> {
+        self.tiles.get(&value).copied().unwrap_or(Some(BASE_MOVE_COST))
+    }
+
+    /// Returns the cost of stepping from `from` onto the adjacent tile `to`, or `None` if the step is not allowed.
+    pub fn step_cost(&self, world: &World, from: TilePos, to: TilePos) -> Option<u32> {
+        let cost = self.cost(world.value(&to)?)?;
+
+        if from.x == to.x || from.y == to.y {
+            return Some(cost);
+        }
+
+        match self.diagonal {
+            DiagonalMovement::Never => None,
+            DiagonalMovement::NoCornerCutting => {
+                let beside_x = TilePos { x: to.x, y: from.y };
+                let beside_y = TilePos { x: from.x, y: to.y };
+                let walkable = |tilepos: &TilePos| world.value(tilepos).and_then(|value| self.cost(value)).is_some();
+
+                (walkable(&beside_x) && walkable(&beside_y)).then_some(diagonal(cost))
             }
+            DiagonalMovement::Always => Some(diagonal(cost)),
+        }
+    }
 
-            next
+    /// Returns a lower bound for the cost of walking between two tiles, which keeps A* optimal.
+    pub fn heuristic(&self, from: TilePos, to: TilePos) -> u32 {
+        let min_cost = self.min_cost;
+        let dx = from.x.abs_diff(to.x);
+        let dy = from.y.abs_diff(to.y);
+
+        match self.diagonal {
+            DiagonalMovement::Never => (dx + dy) * min_cost,
+            DiagonalMovement::NoCornerCutting | DiagonalMovement::Always => {
+                (dx.max(dy) - dx.min(dy)) * min_cost + dx.min(dy) * diagonal(min_cost)
+            }
+        }
+    }
+}
+
+impl Default for MovementCosts {
+    fn default() -> Self {
+        let mut costs = Self {
+            tiles: HashMap::default(),
+            min_cost: BASE_MOVE_COST,
+            diagonal: DiagonalMovement::default(),
+        };
+        costs.set_cost(EMPTY_TILE_ID, None);
+
+        // The bushy edges of the grass are uneven ground
+        for id in GRASS_EDGE_TILE_IDS {
+            costs.set_cost(id, Some(BASE_MOVE_COST * 3 / 2));
+        }
+
+        // Wading through the shallows is slow going
+        for id in SHALLOW_WATER_TILE_IDS {
+            costs.set_cost(id, Some(BASE_MOVE_COST * 3));
+        }
+
+        costs
+    }
+}
+
+// Diagonal steps cover ~1.4 times the distance of orthogonal ones
+fn diagonal(cost: u32) -> u32 {
+    cost * 14 / 10
+}
+
+/// Finds the cheapest path from `start` to `goal`, including both ends.
+///
+/// # Examples
+///
+/// ```
+/// use bevy_ecs_tilemap::prelude::TilePos;
+/// use bevy_game::villager::{find_path, DiagonalMovement, MovementCosts};
+/// use bevy_game::worldgen::{World, TILEMAP_SIZE};
+/// use grid_2d::{Grid, Size};
+///
+/// let world = World {
+///     tiles: Grid::new_copy(Size::new(TILEMAP_SIZE.x, TILEMAP_SIZE.y), 17),
+/// };
+/// let start = TilePos { x: 0, y: 0 };
+/// let goal = TilePos { x: 3, y: 3 };
+///
+/// let mut costs = MovementCosts::default();
+/// assert_eq!(find_path(&world, &costs, start, goal).unwrap().len(), 7);
+///
+/// costs.diagonal = DiagonalMovement::Always;
+/// assert_eq!(find_path(&world, &costs, start, goal).unwrap().len(), 4);
+/// ```
+pub fn find_path(world: &World, costs: &MovementCosts, start: TilePos, goal: TilePos) -> Option<Vec<TilePos>> {
+    let include_diagonals = costs.diagonal != DiagonalMovement::Never;
+
+    astar(
+        &start,
+        |&current| {
+            let neighbors = Neighbors::get_square_neighboring_positions(&current, &TILEMAP_SIZE, include_diagonals);
+
+            neighbors
+                .iter()
+                .filter_map(|&neighbor| Some((neighbor, costs.step_cost(world, current, neighbor)?)))
+                .collect::<Vec<_>>()
         },
-        |&current| current.to_coord().distance2(goal.to_coord()),
+        |&current| costs.heuristic(current, goal),
         |&current| current == goal,
     )
     .map(|(path, _)| path)
 }
 
-fn setup_villagers(mut cmds: Commands, images: Res<CharacterAssets>) {
-    let animation_indices = AnimationIndices { first: 0, last: 7 };
+/// Returns up to `count` tiles of the largest patch of walkable ground, starting from the one closest to the camp and
+/// spreading out from there.
+///
+/// Villagers that started on water, or on an island, would be cut off from most of the map, and so would their beds.
+pub fn camp_tiles(world: &World, costs: &MovementCosts, count: usize) -> Vec<TilePos> {
+    let walkable = |tilepos: &TilePos| world.value(tilepos).and_then(|value| costs.cost(value)).is_some();
+
+    // Flood from `start`, stopping after `limit` tiles
+    let flood = |start: TilePos, seen: &mut HashSet<TilePos>, limit: usize| {
+        let mut tiles = vec![];
+        let mut frontier = VecDeque::from([start]);
+        seen.insert(start);
+        while let Some(tilepos) = frontier.pop_front() {
+            if tiles.len() == limit {
+                break;
+            }
+
+            tiles.push(tilepos);
+            for neighbor in Neighbors::get_square_neighboring_positions(&tilepos, &TILEMAP_SIZE, false).iter() {
+                if walkable(neighbor) && seen.insert(*neighbor) {
+                    frontier.push_back(*neighbor);
+                }
+            }
+        }
+        tiles
+    };
+
+    let mut seen = HashSet::new();
+    let mut largest = vec![];
+    for coord in world.tiles.coord_iter() {
+        let tilepos = TilePos::new(coord.x as u32, coord.y as u32);
+        if walkable(&tilepos) && !seen.contains(&tilepos) {
+            let patch = flood(tilepos, &mut seen, usize::MAX);
+            if patch.len() > largest.len() {
+                largest = patch;
+            }
+        }
+    }
+
+    let distance = |tilepos: &&TilePos| CAMP.x.abs_diff(tilepos.x).pow(2) + CAMP.y.abs_diff(tilepos.y).pow(2);
+    match largest.iter().min_by_key(distance) {
+        Some(&start) => flood(start, &mut HashSet::new(), count),
+        None => vec![],
+    }
+}
+
+fn setup_villagers(
+    mut cmds: Commands,
+    world: Res<World>,
+    costs: Res<MovementCosts>,
+    images: Option<Res<CharacterAssets>>,
+) {
+    // Every villager gets a spot to start on and a bed next to it
+    let tiles = camp_tiles(&world, &costs, 12);
+    for (index, spot) in tiles.chunks_exact(2).enumerate() {
+        let villager = spawn_villager(
+            &mut cmds,
+            images.as_deref(),
+            Transform::from_translation(spot[0].to_world_space().extend(10.0)),
+        );
+        spawn_bed(&mut cmds, spot[1]);
+
+        // Half the villagers know how to mine and the other half how to chop, so both kinds of job can be taken
+        let mut skills = Skills::default();
+        let skill = if index % 2 == 0 {
+            Skill::Mining
+        } else {
+            Skill::Woodcutting
+        };
+        skills.set_level(skill, 1);
+        cmds.entity(villager).insert(skills);
+    }
+}
+
+/// Spawns a villager of the default profession, returning its entity.
+///
+/// The villager is only given a sprite and animations when the character assets are loaded, which they are not
+/// when running headless.
+pub(crate) fn spawn_villager(cmds: &mut Commands, images: Option<&CharacterAssets>, transform: Transform) -> Entity {
+    let mut villager = cmds.spawn((
+        Name::new("Villager"),
+        TransformBundle::from(transform),
+        Speed(24.0),
+        Movement::default(),
+        Needs::default(),
+        Inventory::new(VILLAGER_INVENTORY_SLOTS, VILLAGER_MAX_WEIGHT),
+        WorkPriorities::default(),
+        Skills::default(),
+        // Lets the marquee select the villager
+        Collider::cuboid(ENTITY_SIZE_IN_PIXELS / 2.0, ENTITY_SIZE_IN_PIXELS / 2.0),
+        CollisionGroups::new(SELECTABLE_GROUP, SELECTION_GROUP),
+        // The thinker is built from the profession once the behavior definitions are loaded
+        Profession::default(),
+        Blackboard::default(),
+    ));
 
-    for i in 1..7 {
-        let move_and_gather = Steps::build()
-            .label("MoveAndGather")
-            .step(MoveToNearest::<Bush>::new())
-            .step(GatherAction {});
+    if let Some(images) = images {
+        let animation_indices = AnimationIndices { first: 0, last: 7 };
 
-        // Spawn an animated character using the sprite sheet
-        cmds.spawn((
-            Name::new("Villager"),
+        // Animate the character using the sprite sheet
+        villager.insert((
             SpriteSheetBundle {
                 texture: images.image.clone(),
                 atlas: TextureAtlas {
                     layout: images.layout.clone(),
                     index: animation_indices.first,
                 },
-                transform: Transform::from_xyz(21.0 * 16.0, (25.0 * 16.0) + (i as f32 * 1.0 * 16.0), 10.0),
+                transform,
                 ..Default::default()
             },
             animation_indices,
             AnimationTimer(Timer::new(Duration::from_millis(100), TimerMode::Repeating)),
-            Speed(24.0),
-            Movement::default(),
             AnimationBundle::default(),
-            Thinker::build()
-                .label("FarmerThinker")
-                .picker(FirstToScore::new(1.0))
-                .when(WorkNeedScorer, move_and_gather),
-            Blackboard::default(),
         ));
     }
+
+    villager.id()
 }
 
 #[derive(Clone, Copy, Component)]
@@ -117,13 +418,16 @@ pub struct Speed(f32);
 pub struct Movement {
     pub path: Vec<TilePos>,
     pub direction: SquareDirection,
+    /// How many times in a row no way was found around the blocked next tile of the path
+    repath_attempts: u32,
 }
 
 impl Movement {
-    fn new(path: Vec<TilePos>) -> Self {
+    pub(crate) fn new(path: Vec<TilePos>) -> Self {
         Movement {
             path,
             direction: SquareDirection::South,
+            repath_attempts: 0,
         }
     }
 
@@ -138,14 +442,98 @@ impl Default for Movement {
     }
 }
 
-pub fn movement_system(time: Res<Time>, mut query: Query<(&mut Transform, &Speed, &mut Movement)>) {
+/// Returns the direction, scaled by pace, to walk in to keep heading in `heading` while keeping clear of the
+/// `neighbours`, given as their position and the direction they are walking in.
+///
+/// Villagers steer away from anyone too close, keep to the right when meeting someone head on and slow down to queue
+/// behind someone walking the same way just ahead of them.
+///
+/// # Examples
+///
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_game::villager::steer;
+///
+/// let position = Vec2::ZERO;
+/// let east = Vec2::X;
+///
+/// // Alone, or with someone standing well out of the way, nothing changes
+/// assert_eq!(steer(position, east, []), east);
+/// assert_eq!(steer(position, east, [(Vec2::new(0.0, 40.0), Vec2::ZERO)]), east);
+///
+/// // Someone just to the north pushes the villager south
+/// assert!(steer(position, east, [(Vec2::new(0.0, 6.0), Vec2::ZERO)]).y < 0.0);
+///
+/// // Someone walking east just ahead is queued behind
+/// let queued = steer(position, east, [(Vec2::new(8.0, 0.0), east)]);
+/// assert!(queued.x < 0.7);
+/// ```
+pub fn steer(position: Vec2, heading: Vec2, neighbours: impl IntoIterator<Item = (Vec2, Vec2)>) -> Vec2 {
+    let mut push = Vec2::ZERO;
+    let mut pace = 1.0_f32;
+
+    for (other, other_heading) in neighbours {
+        let offset = other - position;
+        let distance = offset.length();
+        let ahead = offset.dot(heading);
+        let aside = offset.perp_dot(heading).abs();
+        let in_the_way = ahead > 0.0 && ahead < QUEUE_DISTANCE && aside < QUEUE_WIDTH;
+
+        if in_the_way && other_heading.dot(heading) > 0.5 {
+            pace = pace.min(ahead / QUEUE_DISTANCE);
+        } else if in_the_way && other_heading.dot(heading) < -0.5 {
+            // Turning left is `perp`, so keeping right is the opposite
+            push -= heading.perp();
+        }
+
+        if distance < SEPARATION_RADIUS {
+            // Someone standing exactly on top of the villager gives no direction to move away in, so step aside
+            let away = (-offset).try_normalize().unwrap_or(-heading.perp());
+            push += away * (1.0 - distance / SEPARATION_RADIUS);
+        }
+    }
+
+    (heading * pace + push * SEPARATION_WEIGHT).clamp_length_max(1.0)
+}
+
+/// Rebuilds the crowd tree from where the last step left every villager, so that steering does not depend on how
+/// often frames are drawn
+fn update_crowd_tree_system(mut crowd: ResMut<CrowdTree>, walkers: Query<(Entity, &Transform), With<Movement>>) {
+    let points = walkers
+        .iter()
+        .map(|(agent, transform)| Point2::from((agent, transform.translation.xy())))
+        .collect();
+    crowd.tree = KdTree::build_by_ordered_float(points);
+}
+
+pub fn movement_system(
+    time: Res<Time>,
+    world: Res<World>,
+    costs: Res<MovementCosts>,
+    crowd: Res<CrowdTree>,
+    mut pathfinder: ResMut<Pathfinder>,
+    mut query: Query<(Entity, &mut Transform, &Speed, &mut Movement)>,
+    mut path_blocked_writer: EventWriter<PathBlocked>,
+) {
     let delta = time.delta_seconds();
 
     if delta.is_zero() {
         return;
     }
 
-    for (mut transform, speed, mut movement) in query.iter_mut() {
+    // The tree is rebuilt before villagers move, so it is used to find who is close by and the positions are read here
+    let walkers: HashMap<Entity, (Vec2, Vec2)> = query
+        .iter()
+        .map(|(agent, transform, _, movement)| {
+            let position = transform.translation.xy();
+            let heading = movement
+                .target()
+                .map_or(Vec2::ZERO, |target| (target - position).normalize_or_zero());
+            (agent, (position, heading))
+        })
+        .collect();
+
+    for (agent, mut transform, speed, mut movement) in query.iter_mut() {
         if let Some(target) = movement.target() {
             // Check if we have reached the current target
             if transform.translation.xy().distance(target) < 1.0 {
@@ -153,13 +541,80 @@ pub fn movement_system(time: Res<Time>, mut query: Query<(&mut Transform, &Speed
                 movement.path.remove(0);
             }
 
+            // The map can change under a path, so the next tile is checked before every step
+            if let (Some(&next), Some(&goal)) = (movement.path.first(), movement.path.last()) {
+                let walkable = world.value(&next).and_then(|value| costs.cost(value)).is_some();
+                if walkable {
+                    movement.repath_attempts = 0;
+                } else {
+                    let start = transform.translation.xy().to_tilepos();
+                    match pathfinder.query(start, goal) {
+                        PathQuery::Found(mut path) => {
+                            if path.len() > 1 && path.first() == Some(&start) {
+                                path.remove(0);
+                            }
+                            movement.path = path;
+                            movement.repath_attempts = 0;
+                        }
+                        PathQuery::NotFound => {
+                            movement.repath_attempts += 1;
+                            if movement.repath_attempts >= MAX_REPATH_ATTEMPTS {
+                                debug!("{:?} is blocked at {:?} on the way to {:?}", agent, next, goal);
+                                path_blocked_writer.send(PathBlocked { agent, at: next, goal });
+                                movement.path.clear();
+                                movement.repath_attempts = 0;
+                            }
+                            continue;
+                        }
+                        // Wait in front of the blocked tile while the way around is searched for
+                        PathQuery::Pending => continue,
+                    }
+                }
+            }
+
             if let Some(target) = movement.target() {
                 // Calculate and normalize the heading vector towards the current target
-                let heading = transform.translation.xy().towards(&target);
+                let position = transform.translation.xy();
+                let heading = position.towards(&target);
+
+                // Slow down on expensive ground and speed up on cheap ground such as roads
+                let terrain_factor = movement
+                    .path
+                    .first()
+                    .and_then(|tilepos| costs.cost(world.value(tilepos)?))
+                    .map_or(1.0, |cost| BASE_MOVE_COST as f32 / cost as f32);
+
+                let arriving = movement.path.len() == 1 && position.distance(target) < ARRIVAL_RADIUS;
+                let velocity = if arriving {
+                    heading
+                } else {
+                    let neighbours = crowd
+                        .within_distance(position, SEPARATION_RADIUS.max(QUEUE_DISTANCE))
+                        .into_iter()
+                        .filter_map(|(_, neighbour)| neighbour.filter(|&neighbour| neighbour != agent))
+                        .filter_map(|neighbour| walkers.get(&neighbour).copied());
+                    steer(position, heading, neighbours)
+                };
+
+                // Steering must never take a villager off the walkable ground its path keeps to
+                let step = velocity * speed.0 * terrain_factor * delta;
+                let stays_walkable = TilePos::from_world_pos(
+                    &(position + step),
+                    &TILEMAP_SIZE,
+                    &TILEMAP_TILE_SIZE.into(),
+                    &TILEMAP_TYPE,
+                )
+                .and_then(|tilepos| costs.cost(world.value(&tilepos)?))
+                .is_some();
+                let step = if stays_walkable {
+                    step
+                } else {
+                    heading * speed.0 * terrain_factor * delta
+                };
 
                 // Move the villager towards the current target
-                transform.translation.x += heading.x * speed.0 * delta;
-                transform.translation.y += heading.y * speed.0 * delta;
+                transform.translation.x += step.x;
+                transform.translation.y += step.y;
 
                 // Update the direction
                 if let Some(direction) = transform.translation.xy().look_at(&target) {
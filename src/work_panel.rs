@@ -0,0 +1,215 @@
+use crate::jobs::{JobKind, WorkPriorities, LOWEST_PRIORITY};
+use crate::states::States::Play;
+use bevy::prelude::*;
+
+/// Opens and closes the work panel
+const TOGGLE_KEY: KeyCode = KeyCode::KeyP;
+
+const LABEL_WIDTH: f32 = 112.0;
+const CELL_WIDTH: f32 = 64.0;
+
+/// A table of villagers by kinds of work, where clicking a cell cycles the villager's priority for that work
+pub struct WorkPanelPlugin;
+
+impl Plugin for WorkPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                toggle_work_panel_system,
+                priority_cell_button_system,
+                priority_cell_style_system,
+            )
+                .chain()
+                .run_if(in_state(Play)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct WorkPanel;
+
+#[derive(Component)]
+struct PriorityCell {
+    villager: Entity,
+    kind: JobKind,
+}
+
+/// Returns the priority after `priority` when cycling through them: 1, 2, 3, 4, off and back to 1
+fn next_priority(priority: Option<u8>) -> Option<u8> {
+    match priority {
+        None => Some(1),
+        Some(priority) if priority >= LOWEST_PRIORITY => None,
+        Some(priority) => Some(priority + 1),
+    }
+}
+
+fn priority_text(priority: Option<u8>) -> String {
+    priority.map_or("-".to_string(), |priority| priority.to_string())
+}
+
+fn priority_color(priority: Option<u8>) -> Color {
+    match priority {
+        Some(1) => Color::srgb_u8(203, 75, 22),  // Solarized Orange
+        Some(2) => Color::srgb_u8(181, 137, 0),  // Solarized Yellow
+        Some(3) => Color::srgb_u8(133, 153, 0),  // Solarized Green
+        Some(_) => Color::srgb_u8(42, 161, 152), // Solarized Cyan
+        None => Color::srgb_u8(7, 54, 66),       // Solarized Base02
+    }
+}
+
+fn text(value: impl Into<String>) -> TextBundle {
+    TextBundle::from_section(
+        value,
+        TextStyle {
+            font_size: 16.0,
+            color: Color::WHITE,
+            ..default()
+        },
+    )
+}
+
+fn cell_style(width: f32) -> Style {
+    Style {
+        width: Val::Px(width),
+        justify_content: JustifyContent::Center,
+        padding: UiRect::axes(Val::Px(4.0), Val::Px(2.0)),
+        ..default()
+    }
+}
+
+/// Builds the table from the villagers there are when it is opened
+fn toggle_work_panel_system(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    panels: Query<Entity, With<WorkPanel>>,
+    villagers: Query<(Entity, &WorkPriorities)>,
+) {
+    if !keys.just_pressed(TOGGLE_KEY) {
+        return;
+    }
+
+    if let Ok(panel) = panels.get_single() {
+        commands.entity(panel).despawn_recursive();
+        return;
+    }
+
+    let mut villagers: Vec<_> = villagers.iter().collect();
+    villagers.sort_by_key(|(entity, _)| *entity);
+
+    let row = || NodeBundle {
+        style: Style {
+            column_gap: Val::Px(2.0),
+            ..default()
+        },
+        ..default()
+    };
+
+    commands
+        .spawn((
+            WorkPanel,
+            Name::new("Work Panel"),
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(8.0),
+                    right: Val::Px(8.0),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(2.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::srgb_u8(0, 43, 54)), // Solarized Base03
+                ..default()
+            },
+        ))
+        .with_children(|panel| {
+            panel.spawn(row()).with_children(|header| {
+                header.spawn(NodeBundle {
+                    style: cell_style(LABEL_WIDTH),
+                    ..default()
+                });
+
+                for kind in JobKind::ALL {
+                    header
+                        .spawn(NodeBundle {
+                            style: cell_style(CELL_WIDTH),
+                            ..default()
+                        })
+                        .with_children(|cell| {
+                            cell.spawn(text(kind.name()));
+                        });
+                }
+            });
+
+            for (villager, priorities) in villagers {
+                panel.spawn(row()).with_children(|row| {
+                    row.spawn(NodeBundle {
+                        style: cell_style(LABEL_WIDTH),
+                        ..default()
+                    })
+                    .with_children(|label| {
+                        label.spawn(text(format!("Villager {}", villager.index())));
+                    });
+
+                    for kind in JobKind::ALL {
+                        let priority = priorities.get(kind);
+
+                        row.spawn((
+                            PriorityCell { villager, kind },
+                            ButtonBundle {
+                                style: cell_style(CELL_WIDTH),
+                                background_color: BackgroundColor(priority_color(priority)),
+                                ..default()
+                            },
+                        ))
+                        .with_children(|button| {
+                            button.spawn(text(priority_text(priority)));
+                        });
+                    }
+                });
+            }
+        });
+}
+
+fn priority_cell_button_system(
+    cells: Query<(&Interaction, &PriorityCell), Changed<Interaction>>,
+    mut villagers: Query<&mut WorkPriorities>,
+) {
+    for (interaction, cell) in cells.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        // The villager may have died since the panel was opened
+        if let Ok(mut priorities) = villagers.get_mut(cell.villager) {
+            let priority = next_priority(priorities.get(cell.kind));
+            priorities.set(cell.kind, priority);
+        }
+    }
+}
+
+/// Keeps the cells in step with the priorities, however they were changed
+fn priority_cell_style_system(
+    mut cells: Query<(&PriorityCell, &Children, &mut BackgroundColor)>,
+    villagers: Query<Ref<WorkPriorities>>,
+    mut texts: Query<&mut Text>,
+) {
+    for (cell, children, mut background) in cells.iter_mut() {
+        let Ok(priorities) = villagers.get(cell.villager) else {
+            continue;
+        };
+
+        if !priorities.is_changed() {
+            continue;
+        }
+
+        let priority = priorities.get(cell.kind);
+        *background = BackgroundColor(priority_color(priority));
+
+        let mut texts = texts.iter_many_mut(children);
+        while let Some(mut text) = texts.fetch_next() {
+            text.sections[0].value = priority_text(priority);
+        }
+    }
+}
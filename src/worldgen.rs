@@ -6,15 +6,15 @@ use bevy_ecs_tilemap::prelude::*;
 use bevy_rapier2d::geometry::CollisionGroups;
 use bevy_rapier2d::prelude::Collider;
 use grid_2d::{Grid, Size};
-use iyes_progress::{Progress, ProgressSystem};
+use iyes_progress::{Progress, ProgressPlugin, ProgressSystem};
 use noise::{NoiseFn, Perlin};
 use rand::prelude::SliceRandom;
-use rand::{thread_rng, SeedableRng};
+use rand::{thread_rng, Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 use wfc::overlapping::OverlappingPatterns;
 use wfc::Wave;
 
-use crate::agent::Bush;
+use crate::agent::{Bush, Rock};
 use crate::ext::TilePosExt;
 use crate::marquee::{SELECTABLE_GROUP, SELECTION_GROUP};
 use crate::states::States::{LoadPlay, Play, Worldgen};
@@ -36,8 +36,13 @@ pub struct WorldgenPlugin;
 
 impl Plugin for WorldgenPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(TilemapPlugin)
-            .add_systems(Update, (generate_layer.track_progress(),).run_if(in_state(Worldgen)));
+        app.init_resource::<WorldSeed>();
+
+        app.add_plugins((
+            ProgressPlugin::new(Worldgen).continue_to(LoadPlay),
+            ProgressPlugin::new(LoadPlay).continue_to(Play),
+        ))
+        .add_systems(Update, (generate_layer.track_progress(),).run_if(in_state(Worldgen)));
 
         app.add_systems(
             Update,
@@ -45,18 +50,109 @@ impl Plugin for WorldgenPlugin {
         );
 
         app.add_systems(Update, update_tile_transform_system.run_if(in_state(Play)));
+    }
+}
+
+/// Draws the generated tilemaps and frames the camera on the world once play starts
+pub struct WorldgenRenderPlugin;
+
+impl Plugin for WorldgenRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(TilemapPlugin)
+            .add_systems(OnEnter(Play), center_camera_in_world);
+    }
+}
+
+/// The seed that drives every random decision made during worldgen.
+///
+/// Each layer draws from its own [`SeedStream`] so that adding or reordering layers does not shift the
+/// random numbers consumed by the others. Two worlds generated from the same seed are identical tile-for-tile.
+///
+/// The default seed is read from the `WORLD_SEED` environment variable, falling back to a random one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Resource)]
+pub struct WorldSeed(pub u64);
+
+impl WorldSeed {
+    /// Creates an independent random number generator for the given stream.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bevy_game::worldgen::{SeedStream, WorldSeed};
+    /// use rand::Rng;
+    ///
+    /// let seed = WorldSeed(42);
+    /// let a: u64 = seed.rng(SeedStream::Wave(0)).gen();
+    /// let b: u64 = seed.rng(SeedStream::Wave(0)).gen();
+    /// let c: u64 = seed.rng(SeedStream::Wave(1)).gen();
+    /// assert_eq!(a, b);
+    /// assert_ne!(a, c);
+    /// ```
+    pub fn rng(&self, stream: SeedStream) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.0);
+        rng.set_stream(stream.id());
+        rng
+    }
+}
+
+impl Default for WorldSeed {
+    fn default() -> Self {
+        std::env::var("WORLD_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .map(Self)
+            .unwrap_or_else(|| Self(thread_rng().gen()))
+    }
+}
+
+/// The random sub-streams derived from a [`WorldSeed`], one per worldgen decision.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SeedStream {
+    /// Wave function collapse of the tilemap layer with the given index
+    Wave(u32),
+    /// Cosmetic tile variants of the tilemap layer with the given index
+    Variants(u32),
+    /// Placement of resources such as bushes, flowers and stones
+    Resources,
+}
 
-        app.add_systems(OnEnter(Play), center_camera_in_world);
+impl SeedStream {
+    fn id(self) -> u64 {
+        match self {
+            SeedStream::Wave(layer) => (layer as u64) << 2,
+            SeedStream::Variants(layer) => ((layer as u64) << 2) | 1,
+            SeedStream::Resources => 2,
+        }
     }
 }
 
-#[derive(Resource)]
+/// The terrain produced by worldgen, stored as the tile value chosen for every cell of the land layer
+#[derive(Clone, Resource)]
 pub struct World {
-    pub wave: Wave,
-    pub patterns: OverlappingPatterns<u16>,
+    pub tiles: Grid<u16>,
 }
 
-fn generate_layer(mut commands: Commands, assets: Res<AssetServer>, mut next_layer_id: Local<u32>) -> Progress {
+impl World {
+    fn from_wave(wave: &Wave, patterns: &OverlappingPatterns<u16>) -> Self {
+        Self {
+            tiles: Grid::new_grid_map_ref(wave.grid(), |cell| {
+                *patterns.pattern_top_left_value(cell.chosen_pattern_id().unwrap())
+            }),
+        }
+    }
+
+    /// Returns the tile value at the given position, or `None` if it is outside the world.
+    pub fn value(&self, tilepos: &TilePos) -> Option<u16> {
+        self.tiles.get(tilepos.to_coord()).copied()
+    }
+}
+
+fn generate_layer(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    seed: Res<WorldSeed>,
+    mut next_layer_id: Local<u32>,
+) -> Progress {
     // Load hand-crafted pattern made in the Tiled editor
     let mut tiled_loader = tiled::Loader::new();
 
@@ -87,7 +183,10 @@ fn generate_layer(mut commands: Commands, assets: Res<AssetServer>, mut next_lay
         }
 
         // Run wave function collapse
-        let wave = wfc(patterns(pattern.clone()), 3);
+        let wave = wfc(
+            patterns(pattern.clone()),
+            &mut seed.rng(SeedStream::Wave(*next_layer_id)),
+        );
 
         // Get the tileset asset
         let tileset_image = tileset.image.as_ref().expect("Image not found");
@@ -104,15 +203,13 @@ fn generate_layer(mut commands: Commands, assets: Res<AssetServer>, mut next_lay
             &wave,
             tilemap_entity,
             patterns(pattern.clone()),
+            &mut seed.rng(SeedStream::Variants(*next_layer_id)),
         );
 
-        // Store the wave as a resource for use in pathfinding and post-processing
+        // Store the terrain as a resource for use in pathfinding and post-processing
         // Only store the wave generated by the land / grass layer
         if layer.name == "grass" {
-            commands.insert_resource(World {
-                wave,
-                patterns: patterns(pattern.clone()),
-            });
+            commands.insert_resource(World::from_wave(&wave, &patterns(pattern.clone())));
         }
 
         let grid_size = TILEMAP_TILE_SIZE.into();
@@ -148,13 +245,14 @@ fn populate_tilemap(
     wave: &Wave,
     tilemap_entity: Entity,
     patterns: OverlappingPatterns<u16>,
+    rng: &mut impl Rng,
 ) -> Vec<Entity> {
     let mut children = vec![];
 
     for coordinate in wave.grid().coord_iter() {
         let cell = wave.grid().get(coordinate).unwrap();
         let id = cell.chosen_pattern_id().unwrap();
-        let value = variants(*patterns.pattern_top_left_value(id));
+        let value = variants(*patterns.pattern_top_left_value(id), rng);
         let tile_pos = TilePos {
             x: coordinate.x as u32,
             y: coordinate.y as u32,
@@ -187,12 +285,11 @@ fn populate_tilemap(
     children
 }
 
-// u16 -> u16
-fn variants(tilemap_idx: u16) -> u16 {
-    let mut rng = thread_rng();
+// u16, Rng -> u16
+fn variants(tilemap_idx: u16, rng: &mut impl Rng) -> u16 {
     let grass_variants = generate_grass_variants();
     match tilemap_idx {
-        GRASS_TILE_ID => *grass_variants.choose(&mut rng).unwrap(),
+        GRASS_TILE_ID => *grass_variants.choose(rng).unwrap(),
         _ => tilemap_idx,
     }
 }
@@ -216,9 +313,8 @@ fn patterns(pattern: Vec<u16>) -> OverlappingPatterns<u16> {
     )
 }
 
-// OverlappingPatterns<u16>, u64 -> Wave
-fn wfc(patterns: OverlappingPatterns<u16>, seed: u64) -> Wave {
-    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+// OverlappingPatterns<u16>, Rng -> Wave
+fn wfc(patterns: OverlappingPatterns<u16>, rng: &mut impl Rng) -> Wave {
     let global_stats = patterns.global_stats();
 
     let runner = wfc::RunOwn::new_wrap_forbid(
@@ -226,14 +322,19 @@ fn wfc(patterns: OverlappingPatterns<u16>, seed: u64) -> Wave {
         &global_stats,
         wfc::wrap::WrapNone,
         wfc::ForbidNothing,
-        &mut rng,
+        rng,
     );
 
-    runner.collapse_retrying(wfc::retry::NumTimes(20), &mut rng).unwrap()
+    runner.collapse_retrying(wfc::retry::NumTimes(20), rng).unwrap()
 }
 
-fn resource_layer_startup_system(mut commands: Commands, world: Res<World>, assets: Res<AssetServer>) -> Progress {
-    let perlin = Perlin::new(3);
+fn resource_layer_startup_system(
+    mut commands: Commands,
+    world: Res<World>,
+    assets: Res<AssetServer>,
+    seed: Res<WorldSeed>,
+) -> Progress {
+    let perlin = Perlin::new(seed.rng(SeedStream::Resources).gen());
 
     // Define noise scale for resource placement
     let noise_scale = 0.1;
@@ -251,7 +352,7 @@ fn resource_layer_startup_system(mut commands: Commands, world: Res<World>, asse
     let resource_types = [(STONE_TILE_ID, 0.7), (FLOWER_TILE_ID, 0.5), (BUSH_TILE_ID, 0.3)];
 
     // Populate the resource tilemap
-    for coord in world.wave.grid().coord_iter() {
+    for (coord, value) in world.tiles.enumerate() {
         let x = coord.x;
         let y = coord.y;
         let noise_value = perlin.get([x as f64 * noise_scale, y as f64 * noise_scale]);
@@ -260,9 +361,6 @@ fn resource_layer_startup_system(mut commands: Commands, world: Res<World>, asse
             x: x as u32,
             y: y as u32,
         };
-        let wave_tile = world.wave.grid().get(coord).unwrap();
-        let pattern_id = wave_tile.chosen_pattern_id().unwrap();
-        let value = world.patterns.pattern_top_left_value(pattern_id);
 
         // Check if the current tile is grass
         if *value == GRASS_TILE_ID {
@@ -276,18 +374,14 @@ fn resource_layer_startup_system(mut commands: Commands, world: Res<World>, asse
                     });
                     resource_tile_storage.set(&tile_pos, resource_tile.id());
 
-                    if *resource_id == BUSH_TILE_ID {
-                        resource_tile.insert(Name::new("Bush"));
-                        resource_tile.insert(Bush);
-                        resource_tile.insert(TransformBundle::from(Transform {
-                            translation: tile_pos.to_world_space().extend(0.0),
-                            ..default()
-                        }));
-                        resource_tile.insert(Collider::cuboid(
-                            ENTITY_SIZE_IN_PIXELS / 2.0,
-                            ENTITY_SIZE_IN_PIXELS / 2.0,
-                        ));
-                        resource_tile.insert(CollisionGroups::new(SELECTABLE_GROUP, SELECTION_GROUP));
+                    match *resource_id {
+                        BUSH_TILE_ID => {
+                            resource_tile.insert(bush_bundle(tile_pos));
+                        }
+                        STONE_TILE_ID => {
+                            resource_tile.insert(rock_bundle(tile_pos));
+                        }
+                        _ => {}
                     }
 
                     break; // Stop after placing the first valid resource
@@ -316,6 +410,34 @@ fn resource_layer_startup_system(mut commands: Commands, world: Res<World>, asse
     true.into()
 }
 
+/// The components that make a resource tile a selectable, gatherable bush
+pub(crate) fn bush_bundle(tile_pos: TilePos) -> impl Bundle {
+    (
+        Name::new("Bush"),
+        Bush,
+        TransformBundle::from(Transform {
+            translation: tile_pos.to_world_space().extend(0.0),
+            ..default()
+        }),
+        Collider::cuboid(ENTITY_SIZE_IN_PIXELS / 2.0, ENTITY_SIZE_IN_PIXELS / 2.0),
+        CollisionGroups::new(SELECTABLE_GROUP, SELECTION_GROUP),
+    )
+}
+
+/// The components that make a resource tile a selectable, minable rock
+pub(crate) fn rock_bundle(tile_pos: TilePos) -> impl Bundle {
+    (
+        Name::new("Rock"),
+        Rock,
+        TransformBundle::from(Transform {
+            translation: tile_pos.to_world_space().extend(0.0),
+            ..default()
+        }),
+        Collider::cuboid(ENTITY_SIZE_IN_PIXELS / 2.0, ENTITY_SIZE_IN_PIXELS / 2.0),
+        CollisionGroups::new(SELECTABLE_GROUP, SELECTION_GROUP),
+    )
+}
+
 /// Maintain the `Transform` component on tiles so that they can be used in spatial queries
 fn update_tile_transform_system(mut q: Query<(&mut Transform, &TilePos)>) {
     for (mut transform, tilepos) in q.iter_mut() {